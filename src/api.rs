@@ -0,0 +1,88 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use serde_json::json;
+use snarkvm::prelude::*;
+
+use crate::{broadcast::Broadcaster, games::GameRegistry, metrics::METRICS, queue::ProofQueue, server::AppState};
+
+/// REST routes for inspecting the server without joining a game.
+pub fn routes<N: Network>() -> Router<AppState<N>> {
+    Router::new()
+        .route("/health", get(health))
+        .route("/games", get(list_games::<N>))
+        .route("/games/:id", get(get_game::<N>))
+        .route("/prover", get(prover_status))
+        .route("/queue", get(queue_status::<N>))
+        .route("/transactions/:id", get(find_transaction::<N>))
+        .route("/broadcasts", get(list_broadcasts::<N>))
+        .route("/broadcasts/:id", get(get_broadcast::<N>))
+}
+
+async fn health() -> impl IntoResponse {
+    Json(json!({ "status": "ok" }))
+}
+
+async fn list_games<N: Network>(State(games): State<GameRegistry<N>>) -> impl IntoResponse {
+    Json(games.active())
+}
+
+async fn get_game<N: Network>(
+    State(games): State<GameRegistry<N>>,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    match games.get(id) {
+        Some(game) => Ok(Json(game)),
+        None => Err((StatusCode::NOT_FOUND, format!("game {id} not found"))),
+    }
+}
+
+async fn prover_status() -> impl IntoResponse {
+    Json(json!({
+        "queue_depth": METRICS.prover_queue_depth.get(),
+        "proofs": METRICS.proof_latency.get_sample_count(),
+        "broadcast_success": METRICS.broadcasts.with_label_values(&["success"]).get(),
+        "broadcast_failure": METRICS.broadcasts.with_label_values(&["failure"]).get(),
+        "invalid_transactions": METRICS.broadcasts.with_label_values(&["invalid"]).get(),
+    }))
+}
+
+async fn queue_status<N: Network>(State(queue): State<ProofQueue<N>>) -> impl IntoResponse {
+    Json(queue.snapshot())
+}
+
+async fn find_transaction<N: Network>(
+    State(games): State<GameRegistry<N>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let transaction_id = match N::TransactionID::from_str(&id) {
+        Ok(transaction_id) => transaction_id,
+        Err(_) => return Err((StatusCode::BAD_REQUEST, format!("invalid transaction id {id}"))),
+    };
+    match games.find_transaction(&transaction_id) {
+        Some(lookup) => Ok(Json(lookup)),
+        None => Err((StatusCode::NOT_FOUND, format!("transaction {id} not found"))),
+    }
+}
+
+async fn list_broadcasts<N: Network>(State(broadcaster): State<Broadcaster<N>>) -> impl IntoResponse {
+    Json(broadcaster.recent())
+}
+
+async fn get_broadcast<N: Network>(
+    State(broadcaster): State<Broadcaster<N>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let transaction_id = match N::TransactionID::from_str(&id) {
+        Ok(transaction_id) => transaction_id,
+        Err(_) => return Err((StatusCode::BAD_REQUEST, format!("invalid transaction id {id}"))),
+    };
+    match broadcaster.get(&transaction_id) {
+        Some(record) => Ok(Json(record)),
+        None => Err((StatusCode::NOT_FOUND, format!("transaction {id} was not broadcast"))),
+    }
+}
@@ -0,0 +1,288 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, ensure, Result};
+use clap::Parser;
+use snarkvm::{
+    prelude::{ConsensusStorage, Network, PrivateKey, Request, Testnet3},
+    synthesizer::helpers::memory::ConsensusMemory,
+};
+use superbuffer::{
+    board::{is_set, Cell, Fleet, Orientation, Placement, Ship, SIZE},
+    client::{Action, GameConnection, GameEvent, GameState, SBClient},
+    protocol::ClientMessage,
+    node::NodeClient,
+    records::{RecordScanner, RestBlocks},
+};
+use tokio::io::{stdin, AsyncBufReadExt, BufReader};
+
+/// Plays battleship from the terminal.
+#[derive(Debug, Parser)]
+pub struct CLI {
+    #[clap(value_parser=PrivateKey::<Testnet3>::from_str,long)]
+    pub private_key: PrivateKey<Testnet3>,
+
+    #[clap(long, default_value = "ws://127.0.0.1:3000/battleship")]
+    pub server: String,
+
+    #[clap(short, long, default_value = "http://127.0.0.1:3030")]
+    pub query: String,
+}
+
+const HELP: &str = "\
+commands:
+  place <ship> <cell> <h|v>   place a ship, e.g. `place carrier A1 h`
+  fire <cell>                 shoot at the opponent, e.g. `fire C4`
+  check                       ask the node which transactions are confirmed
+  recover                     reload your board and move records from the chain
+  help                        show this help
+  quit                        leave the game";
+
+struct Tui {
+    client: SBClient<Testnet3, ConsensusMemory<Testnet3>>,
+    node: NodeClient,
+    state: GameState<Testnet3>,
+    scanner: RecordScanner<Testnet3>,
+    placements: HashMap<Ship, Placement>,
+    shot: Option<Cell>,
+    sent: bool,
+    /// Moves accepted by the server, each one waits for a proof and a transaction.
+    moves: usize,
+    confirmed: HashSet<<Testnet3 as Network>::TransactionID>,
+    log: Vec<String>,
+}
+
+impl Tui {
+    fn fleet(&self) -> Option<Fleet> {
+        Fleet::new(&self.placements.values().copied().collect::<Vec<_>>()).ok()
+    }
+
+    fn place(&mut self, args: &[&str]) -> Result<()> {
+        let [ship, origin, orientation] = args else {
+            bail!("usage: place <ship> <cell> <h|v>");
+        };
+        let placement = Placement {
+            ship: Ship::from_str(ship)?,
+            origin: Cell::from_str(origin)?,
+            orientation: Orientation::from_str(orientation)?,
+        };
+        let bits = placement.bits()?;
+        let others = self
+            .placements
+            .values()
+            .filter(|other| other.ship != placement.ship)
+            .filter_map(|other| other.bits().ok())
+            .fold(0, |all, bits| all | bits);
+        ensure!(others & bits == 0, "{} overlaps another ship", placement.ship);
+        self.placements.insert(placement.ship, placement);
+        Ok(())
+    }
+
+    /// Authorizes the call the game is waiting for, if this player can make it.
+    fn next_move(&mut self) -> Result<Option<VecDeque<Request<Testnet3>>>> {
+        if self.sent {
+            return Ok(None);
+        }
+        let requests = match self.state.pending_action() {
+            Some(Action::Initialize) => {
+                let (Some(fleet), Some(opponent)) = (self.fleet(), self.state.opponent) else {
+                    return Ok(None);
+                };
+                self.client.initialize_board(&fleet, opponent)?
+            }
+            Some(Action::Offer) => {
+                let board = self.state.board.clone().ok_or_else(|| anyhow!("no board record"))?;
+                self.client.offer_battleship(board)?
+            }
+            Some(Action::Start) => {
+                let board = self.state.board.clone().ok_or_else(|| anyhow!("no board record"))?;
+                let move_start = self.state.last_move.clone().ok_or_else(|| anyhow!("no move record"))?;
+                self.client.start_battleship(board, move_start)?
+            }
+            Some(Action::Play) => {
+                let Some(shot) = self.shot.take() else {
+                    return Ok(None);
+                };
+                let board = self.state.board.clone().ok_or_else(|| anyhow!("no board record"))?;
+                let move_incoming = self.state.last_move.clone().ok_or_else(|| anyhow!("no move record"))?;
+                self.client.play(board, move_incoming, shot)?
+            }
+            None => return Ok(None),
+        };
+        self.sent = true;
+        Ok(Some(requests))
+    }
+
+    fn fire(&mut self, cell: &str) -> Result<()> {
+        ensure!(!self.sent && self.state.my_turn(), "not your turn");
+        let cell = Cell::from_str(cell)?;
+        ensure!(self.state.board_entry("played_tiles") & cell.bit() == 0, "already fired at {cell}");
+        self.shot = Some(cell);
+        self.log.push(format!("fired at {cell}"));
+        Ok(())
+    }
+
+    fn check(&mut self) -> Result<()> {
+        for id in &self.state.transactions {
+            if self.confirmed.contains(id) {
+                continue;
+            }
+            match self.node.transaction::<Testnet3>(id) {
+                Ok(_) => {
+                    self.confirmed.insert(*id);
+                }
+                Err(err) if err.is_not_found() => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(())
+    }
+
+    fn recover(&mut self) -> Result<()> {
+        let found = self.scanner.sync(&RestBlocks::new(self.node.clone()))?;
+        self.state.recover(&self.scanner)?;
+        self.log.push(format!("found {found} records up to block {}", self.scanner.height()));
+        Ok(())
+    }
+
+    fn render(&self) {
+        let ships = if self.state.board.is_some() {
+            self.state.board_entry("ships")
+        } else {
+            self.placements.values().filter_map(|placement| placement.bits().ok()).fold(0, |all, bits| all | bits)
+        };
+        let played = self.state.board_entry("played_tiles");
+        let hits = self.state.board_entry("hits_and_misses");
+
+        println!();
+        println!("   your fleet            opponent");
+        println!("   1 2 3 4 5 6 7 8       1 2 3 4 5 6 7 8");
+        for row in 0..SIZE {
+            let label = (b'A' + row) as char;
+            let mine = (0..SIZE)
+                .map(|col| match (is_set(ships, row, col), is_set(self.state.incoming, row, col)) {
+                    (true, true) => 'X',
+                    (true, false) => '#',
+                    (false, true) => 'o',
+                    (false, false) => '.',
+                })
+                .map(String::from)
+                .collect::<Vec<_>>()
+                .join(" ");
+            let theirs = (0..SIZE)
+                .map(|col| match (is_set(played, row, col), is_set(hits, row, col)) {
+                    (_, true) => 'X',
+                    (true, false) => 'o',
+                    _ => '.',
+                })
+                .map(String::from)
+                .collect::<Vec<_>>()
+                .join(" ");
+            println!(" {label} {mine}     {label} {theirs}");
+        }
+        println!();
+        println!("status: {:?}", self.state.status);
+        let pending = self.moves.saturating_sub(self.state.transactions.len());
+        println!("transactions: {pending} pending proof");
+        for id in &self.state.transactions {
+            let state = if self.confirmed.contains(id) { "confirmed" } else { "broadcast" };
+            println!("  {id} {state}");
+        }
+        for line in self.log.iter().rev().take(5).rev() {
+            println!("> {line}");
+        }
+        match self.state.pending_action() {
+            Some(Action::Initialize) if self.fleet().is_none() => println!("place your ships"),
+            Some(Action::Play) if !self.sent => println!("your turn, fire <cell>"),
+            _ if self.sent => println!("waiting for the server"),
+            _ => println!("waiting for the opponent"),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = CLI::parse();
+    let client = SBClient::new(ConsensusMemory::open(None)?, cli.private_key)?;
+    let node = NodeClient::from_url(&cli.query)?;
+    client.load_program(&node)?;
+    let address = client.address()?;
+    let mut connection = GameConnection::connect(&cli.server, client.private_key()).await?;
+    println!("signed in as {address}, waiting for an opponent");
+    println!("{HELP}");
+
+    let mut tui = Tui {
+        scanner: client.record_scanner()?,
+        client,
+        node,
+        state: GameState::default(),
+        placements: HashMap::new(),
+        shot: None,
+        sent: false,
+        moves: 0,
+        confirmed: HashSet::new(),
+        log: vec![],
+    };
+    let mut lines = BufReader::new(stdin()).lines();
+    loop {
+        tokio::select! {
+            event = connection.next_event() => {
+                let event = event?;
+                match &event {
+                    GameEvent::Status { .. } => {
+                        tui.sent = false;
+                        tui.moves += 1;
+                    }
+                    GameEvent::Error(err) => {
+                        tui.sent = false;
+                        tui.log.push(format!("error: {err}"));
+                    }
+                    GameEvent::Rejected { code, message } => {
+                        tui.sent = false;
+                        tui.log.push(format!("rejected {code:?}: {message}"));
+                    }
+                    GameEvent::Resigned(address) => tui.log.push(format!("{address} resigned")),
+                    GameEvent::Shutdown => {
+                        println!("server is shutting down");
+                        return Ok(());
+                    }
+                    _ => {}
+                }
+                tui.state.apply(&event);
+            }
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(());
+                };
+                let args = line.split_whitespace().collect::<Vec<_>>();
+                let result = match args.as_slice() {
+                    ["place", args @ ..] => tui.place(args),
+                    ["fire", cell] => tui.fire(cell),
+                    ["check"] => tui.check(),
+                    ["recover"] => tui.recover(),
+                    ["help"] => {
+                        println!("{HELP}");
+                        Ok(())
+                    }
+                    ["quit"] => {
+                        connection.send(&ClientMessage::Resign).await?;
+                        return Ok(());
+                    }
+                    [] => Ok(()),
+                    _ => Err(anyhow!("unknown command, try `help`")),
+                };
+                if let Err(err) = result {
+                    tui.log.push(err.to_string());
+                }
+            }
+        }
+        match tui.next_move() {
+            Ok(Some(requests)) => connection.send_move(requests).await?,
+            Ok(None) => {}
+            Err(err) => tui.log.push(format!("authorize: {err}")),
+        }
+        tui.render();
+    }
+}
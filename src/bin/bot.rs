@@ -0,0 +1,59 @@
+use clap::Parser;
+use snarkvm::{prelude::*, synthesizer::helpers::memory::ConsensusMemory};
+use superbuffer::{
+    bot::{Bot, Strategy},
+    client::SBClient,
+    node::NodeClient,
+};
+use tracing::{error, info};
+
+/// Runs headless players against the game server, one per private key.
+/// Pass an even number of keys so the server can pair every bot.
+#[derive(Debug, Parser)]
+pub struct CLI {
+    #[clap(value_parser=PrivateKey::<Testnet3>::from_str, long = "private-key", required = true)]
+    pub private_keys: Vec<PrivateKey<Testnet3>>,
+
+    #[clap(long, default_value = "ws://127.0.0.1:3000/battleship")]
+    pub server: String,
+
+    #[clap(short, long, default_value = "http://127.0.0.1:3030")]
+    pub query: String,
+
+    /// `random` or `hunt`.
+    #[clap(long, default_value = "hunt")]
+    pub strategy: Strategy,
+
+    /// Games each bot plays before exiting.
+    #[clap(long, default_value = "1")]
+    pub games: usize,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+    let cli = CLI::parse();
+
+    let node = NodeClient::from_url(&cli.query)?;
+    let mut handles = vec![];
+    for private_key in cli.private_keys {
+        let client = SBClient::new(ConsensusMemory::open(None)?, private_key)?;
+        client.load_program(&node)?;
+        let address = client.address()?;
+        let mut bot = Bot::new(client, cli.strategy);
+        let server = cli.server.clone();
+        let games = cli.games;
+        handles.push(tokio::spawn(async move {
+            for game in 0..games {
+                match bot.play_game(&server).await {
+                    Ok(outcome) => info!("{address} game {game}: {outcome}"),
+                    Err(err) => error!("{address} game {game}: {err}"),
+                }
+            }
+        }));
+    }
+    for handle in handles {
+        handle.await?;
+    }
+    Ok(())
+}
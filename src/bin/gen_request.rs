@@ -1,53 +1,374 @@
-
-use std::time::Instant;
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Instant,
+};
 
 use ::rand::thread_rng;
-use clap::Parser;
-use snarkvm::{prelude::*, synthesizer::helpers::memory::ConsensusMemory};
-use anyhow::Result;
-use superbuffer::utils::get_deployment;
+use anyhow::{anyhow, bail, ensure, Result};
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+use snarkvm::{
+    circuit::{Aleo, AleoV0},
+    prelude::{ConsensusStore, Identifier, Network, PrivateKey, ProgramID, Request, Testnet3, ToBytes, Value, VM},
+    synthesizer::helpers::memory::ConsensusMemory,
+};
+use superbuffer::{
+    client::{GameConnection, GameEvent},
+    node::NodeClient,
+    utils::{vm_load_deployment, vm_load_deployment_file, vm_load_program_dir, NetworkId},
+};
 
 #[derive(Debug, Parser)]
 pub struct CLI {
-    #[clap(value_parser=PrivateKey::<Testnet3>::from_str,long)]
-    pub private_key: PrivateKey<Testnet3>,
-
-    #[clap(parse(try_from_str))]
-    program_id: ProgramID<Testnet3>,
-
-    #[clap(parse(try_from_str))]
-    function: Identifier<Testnet3>,
-    /// The function inputs.
-    #[clap(parse(try_from_str))]
-    inputs: Vec<Value<Testnet3>>,
-    #[clap(short, long, default_value="http://127.0.0.1:3030")]
+    #[clap(short, long, default_value="http://127.0.0.1:3030", global = true)]
     query: String,
+    /// The network to authorize for.
+    #[clap(long, default_value="testnet3", global = true)]
+    network: NetworkId,
+    /// Load programs from `.aleo` sources in this directory instead of querying a node.
+    #[clap(long, global = true)]
+    program_dir: Option<PathBuf>,
+    /// Load a deployment from a JSON file instead of querying a node, imports first. May be repeated.
+    #[clap(long = "deployment-file", global = true)]
+    deployment_files: Vec<PathBuf>,
+    /// Output format: `json`, `pretty` or `bytes`.
+    #[clap(long, default_value="json", global = true)]
+    format: Format,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Authorizes a single function call.
+    Call(Box<Call>),
+    /// Authorizes every step of a scenario file, feeding the records of earlier steps into later ones.
+    Script(Script),
+}
+
+#[derive(Debug, Parser)]
+struct Call {
+    /// Parsed once the network is known.
+    #[clap(long)]
+    private_key: String,
+
+    program_id: String,
+
+    function: String,
+    /// The function inputs. `@path` reads an input, such as a record, from a file.
+    inputs: Vec<String>,
+    /// Write the requests to this file instead of stdout.
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+    /// Join a game on this server (e.g. `ws://127.0.0.1:3000/battleship`) and send the requests.
+    #[clap(long)]
+    send: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+struct Script {
+    /// A `.yaml`, `.yml` or `.json` scenario.
+    scenario: PathBuf,
+    /// Write one file per step into this directory instead of stdout.
+    #[clap(long)]
+    output_dir: Option<PathBuf>,
+    /// Join a game on this server with every player and play the steps.
+    #[clap(long)]
+    send: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Format {
+    Json,
+    Pretty,
+    Bytes,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Format::Json),
+            "pretty" => Ok(Format::Pretty),
+            "bytes" => Ok(Format::Bytes),
+            _ => bail!("Unknown format {s}, expected json, pretty or bytes"),
+        }
+    }
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Json | Format::Pretty => "json",
+            Format::Bytes => "bin",
+        }
+    }
+
+    /// JSON formats end with a newline; bytes are a little-endian `u32` count followed by the requests.
+    fn encode<N: Network>(&self, requests: &VecDeque<Request<N>>) -> Result<Vec<u8>> {
+        let mut out = match self {
+            Format::Json => serde_json::to_vec(requests)?,
+            Format::Pretty => serde_json::to_vec_pretty(requests)?,
+            Format::Bytes => {
+                let mut out = (requests.len() as u32).to_le_bytes().to_vec();
+                for request in requests {
+                    out.extend(request.to_bytes_le()?);
+                }
+                return Ok(out);
+            }
+        };
+        out.push(b'\n');
+        Ok(out)
+    }
+}
+
+/// A scripted sequence of calls, e.g. a whole battleship game.
+///
+/// ```yaml
+/// program: battleship.aleo
+/// players:
+///   - name: a
+///     private_key: APrivateKey1...
+/// steps:
+///   - player: a
+///     function: initialize_board
+///     inputs: ["34084860461056u64", "551911718912u64", "7u64", "1157425104234217472u64", "aleo1..."]
+///   - player: a
+///     function: offer_battleship
+///     inputs: ["$0.0"]   # output 0 of step 0
+/// ```
+#[derive(Debug, Deserialize)]
+struct Scenario {
+    #[serde(default = "default_program")]
+    program: String,
+    players: Vec<ScenarioPlayer>,
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScenarioPlayer {
+    name: String,
+    private_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Step {
+    player: String,
+    function: String,
+    #[serde(default)]
+    inputs: Vec<String>,
+}
+
+fn default_program() -> String {
+    "battleship.aleo".to_string()
+}
+
+impl Scenario {
+    fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => Ok(serde_yaml::from_str(&text)?),
+            _ => Ok(serde_json::from_str(&text)?),
+        }
+    }
+}
+
+/// Resolves an input: `@path` reads it from a file, `$step.output` refers to an earlier step.
+fn resolve_input<N: Network>(input: &str, outputs: &[Vec<Value<N>>]) -> Result<Value<N>> {
+    if let Some(path) = input.strip_prefix('@') {
+        return Value::from_str(fs::read_to_string(path)?.trim());
+    }
+    if let Some(reference) = input.strip_prefix('$') {
+        let (step, index) = reference.split_once('.').ok_or_else(|| anyhow!("Expected $step.output, got {input}"))?;
+        let (step, index): (usize, usize) = (step.parse()?, index.parse()?);
+        return outputs
+            .get(step)
+            .and_then(|outputs| outputs.get(index))
+            .cloned()
+            .ok_or_else(|| anyhow!("Step {step} has no output {index}"));
+    }
+    Value::from_str(input)
+}
+
+fn write_output(path: Option<&Path>, bytes: &[u8]) -> Result<()> {
+    match path {
+        Some(path) => fs::write(path, bytes)?,
+        None => std::io::stdout().write_all(bytes)?,
+    }
+    Ok(())
+}
+
+/// Where the programs to authorize against come from.
+enum Source {
+    Query(NodeClient),
+    /// Offline: deployment files and/or program sources, nothing is fetched from the network.
+    Local { program_dir: Option<PathBuf>, deployment_files: Vec<PathBuf> },
+}
+
+impl Source {
+    fn load_vm<N: Network>(&self, program_id: &ProgramID<N>) -> Result<VM<N, ConsensusMemory<N>>> {
+        let store = ConsensusStore::<N, ConsensusMemory<N>>::open(None)?;
+        let vm = VM::from(store)?;
+        match self {
+            Source::Query(node) => vm_load_deployment(&vm, node, program_id)?,
+            Source::Local { program_dir, deployment_files } => {
+                for path in deployment_files {
+                    eprintln!("load deployment: {}", vm_load_deployment_file(&vm, path)?);
+                }
+                if let Some(dir) = program_dir {
+                    vm_load_program_dir(&vm, dir, program_id)?;
+                }
+                ensure!(vm.contains_program(program_id), "{program_id} is not in the program dir or deployment files");
+            }
+        }
+        eprintln!("deploy: {}", program_id);
+        Ok(vm)
+    }
+}
+
+/// Waits until the server has paired the connection with an opponent.
+async fn wait_started<N: Network>(connection: &mut GameConnection<N>) -> Result<()> {
+    loop {
+        if let GameEvent::Started { seat, opponent } = connection.next_event().await? {
+            eprintln!("{} joined as {:?} against {}", connection.address(), seat, opponent);
+            return Ok(());
+        }
+    }
+}
+
+/// Sends a move and waits for the server to accept or reject it.
+async fn play<N: Network>(connection: &mut GameConnection<N>, requests: VecDeque<Request<N>>) -> Result<()> {
+    connection.send_move(requests).await?;
+    wait_status(connection).await
+}
+
+async fn wait_status<N: Network>(connection: &mut GameConnection<N>) -> Result<()> {
+    loop {
+        match connection.next_event().await? {
+            GameEvent::Status { status, .. } => {
+                eprintln!("status: {:?}", status);
+                return Ok(());
+            }
+            GameEvent::Rejected { code, message } => bail!("Rejected {:?}: {}", code, message),
+            GameEvent::Error(err) => bail!("Server error: {}", err),
+            _ => {}
+        }
+    }
 }
 
 impl CLI {
-    pub fn execute(self) -> Result<()> {
+    pub async fn execute(self) -> Result<()> {
+        let source = if self.program_dir.is_none() && self.deployment_files.is_empty() {
+            Source::Query(NodeClient::from_url(&self.query)?)
+        } else {
+            Source::Local { program_dir: self.program_dir, deployment_files: self.deployment_files }
+        };
+        match self.network {
+            NetworkId::Testnet3 => self.command.execute::<Testnet3, AleoV0>(&source, self.format).await,
+        }
+    }
+}
+
+impl Command {
+    async fn execute<N: Network, A: Aleo<Network = N>>(self, source: &Source, format: Format) -> Result<()> {
+        match self {
+            Command::Call(call) => call.execute::<N>(source, format).await,
+            Command::Script(script) => script.execute::<N, A>(source, format).await,
+        }
+    }
+}
+
+impl Call {
+    async fn execute<N: Network>(self, source: &Source, format: Format) -> Result<()> {
         let rng = &mut thread_rng();
-        let store = ConsensusStore::<Testnet3, ConsensusMemory<Testnet3>>::open(None)?;
-        let vm = VM::from(store)?;
-        let program: Program<Testnet3> =
-        ureq::get(&format!("{}/testnet3/program/{}", self.query, self.program_id)).call()?.into_json()?;
-        for (dep_program, _) in program.imports() {
-            let deployment = get_deployment(&self.query, dep_program)?.unwrap();
-            vm.process().write().load_deployment(&deployment)?;
-        }
-        println!("deploy: {}", program.id());
-        let deployment = get_deployment(&self.query, program.id())?.unwrap();
-        vm.process().write().load_deployment(&deployment)?;
+        let private_key = PrivateKey::<N>::from_str(&self.private_key)?;
+        let program_id = ProgramID::<N>::from_str(&self.program_id)?;
+        let function = Identifier::<N>::from_str(&self.function)?;
+        let vm = source.load_vm(&program_id)?;
+        let inputs = self.inputs.iter().map(|input| resolve_input(input, &[])).collect::<Result<Vec<_>>>()?;
         let start = Instant::now();
-        let authorization = vm.authorize(&self.private_key, self.program_id, self.function, self.inputs.iter(), rng)?;
+        let authorization = vm.authorize(&private_key, program_id, function, inputs, rng)?;
         let requests = authorization.to_vec_deque();
+        eprintln!("elapsed: {}", start.elapsed().as_secs_f64());
+
+        write_output(self.output.as_deref(), &format.encode(&requests)?)?;
+        if let Some(url) = &self.send {
+            let mut connection = GameConnection::connect(url, &private_key).await?;
+            wait_started(&mut connection).await?;
+            play(&mut connection, requests).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Script {
+    async fn execute<N: Network, A: Aleo<Network = N>>(self, source: &Source, format: Format) -> Result<()> {
+        let rng = &mut thread_rng();
+        let scenario = Scenario::load(&self.scenario)?;
+        let program_id = ProgramID::<N>::from_str(&scenario.program)?;
+        let vm = source.load_vm(&program_id)?;
+        let keys = scenario
+            .players
+            .iter()
+            .map(|player| Ok((player.name.clone(), PrivateKey::<N>::from_str(&player.private_key)?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        let mut connections = HashMap::new();
+        if let Some(url) = &self.send {
+            // Join in scenario order, the server seats the first player as A.
+            for player in &scenario.players {
+                connections.insert(player.name.clone(), GameConnection::connect(url, &keys[&player.name]).await?);
+            }
+            for connection in connections.values_mut() {
+                wait_started(connection).await?;
+            }
+        }
+        if let Some(dir) = &self.output_dir {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut outputs: Vec<Vec<Value<N>>> = vec![];
+        for (index, step) in scenario.steps.iter().enumerate() {
+            let private_key = keys.get(&step.player).ok_or_else(|| anyhow!("Unknown player {}", step.player))?;
+            let inputs = step.inputs.iter().map(|input| resolve_input(input, &outputs)).collect::<Result<Vec<_>>>()?;
+            let start = Instant::now();
+            let authorization = vm.authorize(private_key, program_id, step.function.as_str(), inputs, rng)?;
+            let requests = authorization.to_vec_deque();
+            eprintln!("step {index} {} {}: elapsed {}", step.player, step.function, start.elapsed().as_secs_f64());
+
+            // Evaluate locally so later steps can spend the records this one creates.
+            let response = vm.process().read().evaluate::<A>(authorization.replicate())?;
+            outputs.push(response.outputs().to_vec());
 
-        println!("{}", serde_json::to_string(&requests)?);
-        println!("elapsed: {}", start.elapsed().as_secs_f64());
+            let bytes = format.encode(&requests)?;
+            match &self.output_dir {
+                Some(dir) => {
+                    let path = dir.join(format!("{index:02}_{}.{}", step.function, format.extension()));
+                    write_output(Some(&path), &bytes)?;
+                }
+                None => write_output(None, &bytes)?,
+            }
+            if let Some(connection) = connections.get_mut(&step.player) {
+                play(connection, requests).await?;
+                // Every player is told about the move, consume that status on the other connections too.
+                for (name, connection) in connections.iter_mut() {
+                    if name != &step.player {
+                        wait_status(connection).await?;
+                    }
+                }
+            }
+        }
         Ok(())
     }
 }
-fn main() {
+
+#[tokio::main]
+async fn main() -> Result<()> {
     let cli = CLI::parse();
-    cli.execute().unwrap();
+    cli.execute().await
 }
@@ -0,0 +1,197 @@
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use ::rand::thread_rng;
+use anyhow::{ensure, Result};
+use axum::{
+    extract::{Query as QueryParams, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use clap::Parser;
+use serde::Deserialize;
+use serde_json::json;
+use snarkvm::{
+    circuit::{Aleo, AleoV0},
+    prelude::{Authorization, ConsensusStore, Execution, Network, ProgramID, Query, Request, Testnet3, Transaction, VM},
+    synthesizer::helpers::memory::ConsensusMemory,
+};
+use superbuffer::{
+    node::{NodeClient, NodeConfig, QueryProxy},
+    utils::{network_path, vm_load_deployment, vm_prewarm_keys, NetworkId},
+};
+use tokio::sync::Semaphore;
+use tracing::{error, info, warn};
+
+/// Proves authorizations for game servers started with `--prover-worker`.
+#[derive(Debug, Parser)]
+pub struct CLI {
+    /// Node REST endpoint, repeat to fail over between several nodes.
+    #[clap(long = "query", default_value="http://127.0.0.1:3030")]
+    queries: Vec<String>,
+    /// Bearer token sent to the nodes.
+    #[clap(long)]
+    node_token: Option<String>,
+    #[clap(long, default_value="testnet3")]
+    network: NetworkId,
+    #[clap(long, default_value="127.0.0.1:4000")]
+    listen: SocketAddr,
+    /// Bearer token game servers must send to `/prove`, given to them with --prover-token.
+    #[clap(long)]
+    token: Option<String>,
+    /// Proofs run at the same time; further jobs are turned away with 503.
+    #[clap(long, default_value="1")]
+    capacity: usize,
+    /// Programs to load from the node, imports are loaded along with them. Repeatable.
+    #[clap(long = "program", default_value="battleship.aleo")]
+    programs: Vec<String>,
+    /// Directory the proving keys are cached in between restarts.
+    #[clap(long, default_value="keys")]
+    key_cache: PathBuf,
+}
+
+struct Worker<N: Network> {
+    vm: VM<N, ConsensusMemory<N>>,
+    query: QueryProxy,
+    /// Shared with the proving jobs, which hold a permit until they are done.
+    slots: Arc<Semaphore>,
+    capacity: usize,
+    proofs: AtomicU64,
+    token: Option<String>,
+}
+
+impl<N: Network> Worker<N> {
+    fn prove(&self, requests: Vec<Request<N>>) -> Result<Execution<N>> {
+        let authorization = Authorization::new(&requests);
+        let query = Query::from(self.query.url());
+        let (_response, execution, _metrics) =
+            self.vm.execute_authorization_raw(authorization, Some(query), &mut thread_rng())?;
+        self.proofs.fetch_add(1, Ordering::Relaxed);
+        Ok(execution)
+    }
+
+    fn authorized(&self, headers: &HeaderMap) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        let sent = headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok()).unwrap_or_default();
+        let expected = format!("Bearer {token}");
+        // Compared in constant time, the token must not leak through response timings.
+        sent.len() == expected.len() && sent.bytes().zip(expected.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+}
+
+#[derive(Deserialize)]
+struct ProveParams {
+    /// Answer with the fee-less transaction instead of the execution.
+    #[serde(default)]
+    transaction: bool,
+}
+
+async fn prove<N: Network>(
+    State(worker): State<Arc<Worker<N>>>,
+    QueryParams(params): QueryParams<ProveParams>,
+    headers: HeaderMap,
+    Json(requests): Json<Vec<Request<N>>>,
+) -> Response {
+    if !worker.authorized(&headers) {
+        return (StatusCode::UNAUTHORIZED, "Missing or wrong bearer token").into_response();
+    }
+    if requests.is_empty() {
+        return (StatusCode::BAD_REQUEST, "No requests").into_response();
+    }
+    // The permit moves into the proving job: a dropped request does not free the slot while the proof runs.
+    let Ok(slot) = worker.slots.clone().try_acquire_owned() else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "All proving slots are busy").into_response();
+    };
+    let function = format!("{}/{}", requests[0].program_id(), requests[0].function_name());
+    let proving = worker.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let _slot = slot;
+        proving.prove(requests)
+    })
+    .await;
+    match result {
+        Ok(Ok(execution)) if params.transaction => match Transaction::from_execution(execution, None) {
+            Ok(transaction) => {
+                info!("Proved {} as transaction {}", function, transaction.id());
+                Json(transaction).into_response()
+            }
+            Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        },
+        Ok(Ok(execution)) => {
+            info!("Proved {}", function);
+            Json(execution).into_response()
+        }
+        Ok(Err(err)) => {
+            error!("prove {}: {}", function, err);
+            (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()).into_response()
+        }
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn health<N: Network>(State(worker): State<Arc<Worker<N>>>) -> impl IntoResponse {
+    Json(json!({
+        "status": "ok",
+        "network": network_path::<N>().unwrap_or("unknown"),
+        "capacity": worker.capacity,
+        "busy": worker.capacity - worker.slots.available_permits(),
+        "proofs": worker.proofs.load(Ordering::Relaxed),
+    }))
+}
+
+async fn run<N: Network, A: Aleo<Network = N>>(cli: CLI) -> Result<()> {
+    ensure!(cli.capacity > 0, "--capacity must be at least 1");
+    if cli.token.is_none() && !cli.listen.ip().is_loopback() {
+        warn!("Anyone who can reach {} may queue proofs, set --token", cli.listen);
+    }
+    let node = NodeClient::new(NodeConfig {
+        endpoints: cli.queries,
+        token: cli.node_token,
+        // The node is only asked for deployments, state roots and state paths.
+        timeout: Duration::from_secs(30),
+        ..Default::default()
+    })?;
+    let vm = VM::from(ConsensusStore::<N, ConsensusMemory<N>>::open(None)?)?;
+    for program in &cli.programs {
+        let program_id = ProgramID::from_str(program)?;
+        vm_load_deployment(&vm, &node, &program_id)?;
+        info!("Loaded {}, preparing proving keys", program);
+        vm_prewarm_keys::<N, _, A>(&vm, &program_id, Some(&cli.key_cache))?;
+    }
+    let worker = Arc::new(Worker {
+        vm,
+        query: QueryProxy::start::<N>(node)?,
+        slots: Arc::new(Semaphore::new(cli.capacity)),
+        capacity: cli.capacity,
+        proofs: AtomicU64::new(0),
+        token: cli.token,
+    });
+    let app = Router::new()
+        .route("/prove", post(prove::<N>))
+        .route("/health", get(health::<N>))
+        .with_state(worker);
+    info!("Listening on {} with {} proving slots", cli.listen, cli.capacity);
+    axum::Server::bind(&cli.listen).serve(app.into_make_service()).await?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+    let cli = CLI::parse();
+    match cli.network {
+        NetworkId::Testnet3 => run::<Testnet3, AleoV0>(cli).await,
+    }
+}
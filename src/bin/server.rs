@@ -1,19 +1,181 @@
+use std::{path::PathBuf, time::Duration};
+
 use snarkvm::circuit::AleoV0;
-use superbuffer::server::run;
+use superbuffer::{broadcast::{BroadcastConfig, BroadcastStrategy}, limits::LimitsConfig, queue::QueueConfig, node::NodeConfig, player::KeepAlive, protocol::RecordMode, prover::{ProverConfig, RemoteConfig}, server::{run, ServerConfig, TlsConfig}, utils::NetworkId};
 use clap::Parser;
-use snarkvm::prelude::*;
+use snarkvm::prelude::Testnet3;
+use anyhow::{anyhow, Result};
 
 
 #[derive(Debug, Parser)]
 pub struct CLI {
-    #[clap(long, default_value="http://127.0.0.1:3030")]
-    query: String,
+    /// Node REST endpoint, repeat to fail over between several nodes.
+    #[clap(long = "query", default_value="http://127.0.0.1:3030")]
+    queries: Vec<String>,
+    /// Seconds before a node request times out.
+    #[clap(long, default_value="10")]
+    node_timeout: u64,
+    /// Retries, with exponential backoff, after a node could not be reached.
+    #[clap(long, default_value="3")]
+    node_retries: u32,
+    /// Bearer token sent to the nodes.
+    #[clap(long)]
+    node_token: Option<String>,
+    /// The network of the node at --query, e.g. a local devnet runs testnet3 too.
+    #[clap(long, default_value="testnet3")]
+    network: NetworkId,
+    /// Node to broadcast transactions to, repeatable. Defaults to the --query nodes.
+    #[clap(long = "broadcast-to")]
+    broadcast_peers: Vec<String>,
+    /// How many peers must accept a transaction: first-success, all or quorum:<n>.
+    #[clap(long, default_value="first-success")]
+    broadcast_strategy: BroadcastStrategy,
+    /// Seconds after which a transaction the network has not seen is broadcast again.
+    #[clap(long, default_value="120")]
+    rebroadcast_after: u64,
+    /// Broadcast attempts before giving up on a transaction.
+    #[clap(long, default_value="5")]
+    broadcast_attempts: u32,
+    /// Proof priority of a battleship function as `function=level`, higher first. Repeatable,
+    /// defaults to play=3, start_battleship=2, offer_battleship=2, initialize_board=1.
+    #[clap(long = "priority", value_parser = parse_priority)]
+    priorities: Vec<(String, u32)>,
+    /// Address whose moves are proven with --priority-bonus, e.g. a paying player. Repeatable.
+    #[clap(long = "priority-address")]
+    priority_addresses: Vec<String>,
+    #[clap(long, default_value="2")]
+    priority_bonus: u32,
+    /// Seconds a queued proof waits to gain a priority level, so low priorities do not starve.
+    #[clap(long, default_value="60")]
+    queue_aging: u64,
+    /// Seconds to keep proving queued requests after SIGINT/SIGTERM.
+    #[clap(long, default_value="60")]
+    shutdown_timeout: u64,
+    /// Directory for requests that could not be proven before shutdown.
+    #[clap(long, default_value="pending")]
+    pending_dir: PathBuf,
+    /// Seconds between server pings to a player.
+    #[clap(long, default_value="15")]
+    ping_interval: u64,
+    /// Seconds of silence after which a player is considered disconnected.
+    #[clap(long, default_value="60")]
+    idle_timeout: u64,
+    /// How players receive their records: encrypted, commitment or plaintext (needed by clients before protocol version 2).
+    #[clap(long, default_value="encrypted")]
+    records: RecordMode,
+    /// PEM certificate chain, serves https:// and wss:// together with --tls-key. Reloaded when it changes.
+    #[clap(long, requires="tls-key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key for --tls-cert.
+    #[clap(long, requires="tls-cert")]
+    tls_key: Option<PathBuf>,
+    /// Evaluate moves and run the game without proving or broadcasting, for play-testing and CI.
+    #[clap(long, conflicts_with="prover-workers")]
+    dry_run: bool,
+    /// Prover worker to send proofs to instead of proving in process, repeatable.
+    #[clap(long = "prover-worker")]
+    prover_workers: Vec<String>,
+    /// Bearer token sent to the prover workers, see the worker's --token.
+    #[clap(long, requires="prover-workers")]
+    prover_token: Option<String>,
+    /// Seconds a prover worker may take for one proof.
+    #[clap(long, default_value="600")]
+    prover_timeout: u64,
+    /// Directory with battleship.aleo and its imports, loaded instead of the deployment on the node.
+    #[clap(long)]
+    program_dir: Option<PathBuf>,
+    /// Directory the proving keys are cached in between restarts.
+    #[clap(long, default_value="keys")]
+    key_cache: PathBuf,
+    /// Game connections per IP and per address and minute, 0 for no limit.
+    #[clap(long, default_value="10")]
+    connections_per_minute: u32,
+    /// Messages per IP and per address and minute, 0 for no limit.
+    #[clap(long, default_value="120")]
+    messages_per_minute: u32,
+    /// Moves, and so proofs, per IP and per address and hour, 0 for no limit.
+    #[clap(long, default_value="300")]
+    moves_per_hour: u32,
+    /// Games an address may play at the same time.
+    #[clap(long, default_value="2")]
+    max_games_per_address: usize,
+    /// Largest WebSocket message accepted, in bytes.
+    #[clap(long, default_value="65536")]
+    max_message_size: usize,
+    /// Only these addresses may play. Repeatable, everyone may play if not given.
+    #[clap(long = "allow-address")]
+    allow_addresses: Vec<String>,
+    /// Address that may not play. Repeatable.
+    #[clap(long = "deny-address")]
+    deny_addresses: Vec<String>,
+}
+
+fn parse_priority(priority: &str) -> Result<(String, u32)> {
+    let (function, level) = priority.split_once('=').ok_or_else(|| anyhow!("Expected function=level"))?;
+    Ok((function.to_string(), level.parse()?))
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
     let cli = CLI::parse();
 
-    run::<Testnet3, AleoV0>(&cli.query).await
+    let config = ServerConfig {
+        node: NodeConfig {
+            endpoints: cli.queries,
+            timeout: Duration::from_secs(cli.node_timeout),
+            retries: cli.node_retries,
+            token: cli.node_token,
+            ..Default::default()
+        },
+        broadcast: BroadcastConfig {
+            peers: cli.broadcast_peers,
+            strategy: cli.broadcast_strategy,
+            rebroadcast_after: Duration::from_secs(cli.rebroadcast_after),
+            max_attempts: cli.broadcast_attempts,
+        },
+        queue: {
+            let mut queue = QueueConfig {
+                priority_addresses: cli.priority_addresses,
+                priority_bonus: cli.priority_bonus,
+                aging: Duration::from_secs(cli.queue_aging),
+                ..Default::default()
+            };
+            queue.priorities.extend(cli.priorities);
+            queue
+        },
+        shutdown_timeout: Duration::from_secs(cli.shutdown_timeout),
+        pending_dir: cli.pending_dir,
+        keepalive: KeepAlive {
+            ping_interval: Duration::from_secs(cli.ping_interval),
+            idle_timeout: Duration::from_secs(cli.idle_timeout),
+        },
+        records: cli.records,
+        tls: cli.tls_cert.zip(cli.tls_key).map(|(cert, key)| TlsConfig { cert, key }),
+        prover: if cli.dry_run {
+            ProverConfig::Mock
+        } else if cli.prover_workers.is_empty() {
+            ProverConfig::Local
+        } else {
+            ProverConfig::Remote(RemoteConfig {
+                workers: cli.prover_workers,
+                timeout: Duration::from_secs(cli.prover_timeout),
+                token: cli.prover_token,
+            })
+        },
+        program_dir: cli.program_dir,
+        key_cache: Some(cli.key_cache),
+        limits: LimitsConfig {
+            connections_per_minute: cli.connections_per_minute,
+            messages_per_minute: cli.messages_per_minute,
+            moves_per_hour: cli.moves_per_hour,
+            max_games_per_address: cli.max_games_per_address,
+            max_message_size: cli.max_message_size,
+            allow: cli.allow_addresses,
+            deny: cli.deny_addresses,
+        },
+    };
+    match cli.network {
+        NetworkId::Testnet3 => run::<Testnet3, AleoV0>(config).await,
+    }
 }
@@ -0,0 +1,543 @@
+//! Encoding of the 8x8 battleship grid into the `u64` bitboards used by
+//! `battleship.aleo`. The top-left cell is the most significant bit and
+//! cells are numbered row by row.
+
+use std::{fmt, str::FromStr};
+
+use ::rand::Rng;
+use anyhow::{anyhow, bail, ensure, Result};
+
+pub const SIZE: u8 = 8;
+
+/// A cell of the grid, written as a row letter `A`-`H` and a column `1`-`8`, e.g. `C4`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Cell {
+    pub row: u8,
+    pub col: u8,
+}
+
+impl Cell {
+    pub fn new(row: u8, col: u8) -> Result<Self> {
+        ensure!(row < SIZE && col < SIZE, "Cell ({row}, {col}) is off the board");
+        Ok(Self { row, col })
+    }
+
+    /// The single bit encoding this cell, also the `shoot` input of `play`.
+    pub fn bit(&self) -> u64 {
+        cell_bit(self.row, self.col)
+    }
+
+    /// Decodes a single-bit bitboard, such as a shot, back into its cell.
+    pub fn from_bit(bits: u64) -> Result<Self> {
+        ensure!(bits.count_ones() == 1, "Expected exactly one cell, got {}", bits.count_ones());
+        let index = bits.leading_zeros() as u8;
+        Ok(Self { row: index / SIZE, col: index % SIZE })
+    }
+
+    pub fn all() -> impl Iterator<Item = Cell> + Clone {
+        (0..SIZE).flat_map(|row| (0..SIZE).map(move |col| Cell { row, col }))
+    }
+
+    /// The horizontal and vertical neighbours that are on the board.
+    pub fn neighbours(&self) -> impl Iterator<Item = Cell> {
+        let (row, col) = (self.row as i8, self.col as i8);
+        [(row - 1, col), (row + 1, col), (row, col - 1), (row, col + 1)]
+            .into_iter()
+            .filter_map(|(row, col)| Cell::new(u8::try_from(row).ok()?, u8::try_from(col).ok()?).ok())
+    }
+}
+
+impl FromStr for Cell {
+    type Err = anyhow::Error;
+
+    fn from_str(cell: &str) -> Result<Self> {
+        let (row, col) = parse_cell(cell)?;
+        Ok(Self { row, col })
+    }
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", (b'A' + self.row) as char, self.col + 1)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Ship {
+    Carrier,
+    Battleship,
+    Cruiser,
+    Destroyer,
+}
+
+impl Ship {
+    /// Every ship of a fleet, in the order `initialize_board` takes them.
+    pub const ALL: [Ship; 4] = [Ship::Carrier, Ship::Battleship, Ship::Cruiser, Ship::Destroyer];
+
+    pub fn length(&self) -> u8 {
+        match self {
+            Ship::Carrier => 5,
+            Ship::Battleship => 4,
+            Ship::Cruiser => 3,
+            Ship::Destroyer => 2,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Ship::Carrier => "carrier",
+            Ship::Battleship => "battleship",
+            Ship::Cruiser => "cruiser",
+            Ship::Destroyer => "destroyer",
+        }
+    }
+
+    fn index(&self) -> usize {
+        Ship::ALL.iter().position(|ship| ship == self).unwrap()
+    }
+}
+
+impl FromStr for Ship {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Ship::ALL
+            .into_iter()
+            .find(|ship| ship.name() == name)
+            .ok_or_else(|| anyhow!("Unknown ship {name}, expected one of carrier, battleship, cruiser, destroyer"))
+    }
+}
+
+impl fmt::Display for Ship {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Orientation {
+    Horizontal,
+    Vertical,
+}
+
+impl FromStr for Orientation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "h" | "horizontal" => Ok(Orientation::Horizontal),
+            "v" | "vertical" => Ok(Orientation::Vertical),
+            _ => bail!("Orientation must be h or v"),
+        }
+    }
+}
+
+/// A ship placed on the grid, running right or down from `origin`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Placement {
+    pub ship: Ship,
+    pub origin: Cell,
+    pub orientation: Orientation,
+}
+
+impl Placement {
+    pub fn bits(&self) -> Result<u64> {
+        ship_bits(
+            self.ship.length(),
+            self.origin.row,
+            self.origin.col,
+            self.orientation == Orientation::Horizontal,
+        )
+    }
+}
+
+/// A complete, legal set of ships: every ship exactly once, straight and without overlaps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fleet {
+    bitboards: [u64; 4],
+}
+
+impl Fleet {
+    pub fn new(placements: &[Placement]) -> Result<Self> {
+        ensure!(placements.len() == Ship::ALL.len(), "A fleet has {} ships", Ship::ALL.len());
+        let mut bitboards = [0; 4];
+        for placement in placements {
+            let slot = &mut bitboards[placement.ship.index()];
+            ensure!(*slot == 0, "{} placed twice", placement.ship);
+            *slot = placement.bits()?;
+        }
+        Self::from_bitboards(bitboards)
+    }
+
+    /// Validates the ship bitboards as taken by `initialize_board`.
+    pub fn from_bitboards(bitboards: [u64; 4]) -> Result<Self> {
+        let mut occupied = 0;
+        for (ship, bits) in Ship::ALL.iter().zip(bitboards) {
+            validate_ship(*ship, bits)?;
+            ensure!(occupied & bits == 0, "{ship} overlaps another ship");
+            occupied |= bits;
+        }
+        Ok(Self { bitboards })
+    }
+
+    /// Places every ship at a random position without overlaps.
+    pub fn random<R: Rng>(rng: &mut R) -> Self {
+        let mut bitboards = [0; 4];
+        let mut occupied = 0;
+        for (slot, ship) in bitboards.iter_mut().zip(Ship::ALL) {
+            loop {
+                let horizontal = rng.gen_bool(0.5);
+                let Ok(bits) = ship_bits(ship.length(), rng.gen_range(0..SIZE), rng.gen_range(0..SIZE), horizontal) else {
+                    continue;
+                };
+                if bits & occupied == 0 {
+                    occupied |= bits;
+                    *slot = bits;
+                    break;
+                }
+            }
+        }
+        Self { bitboards }
+    }
+
+    pub fn bitboards(&self) -> [u64; 4] {
+        self.bitboards
+    }
+
+    /// The ship bitboards as `initialize_board` input literals, e.g. `34084860461056u64`.
+    pub fn to_inputs(&self) -> [String; 4] {
+        self.bitboards.map(|bits| format!("{bits}u64"))
+    }
+
+    /// Parses `initialize_board` input literals back into a fleet.
+    pub fn from_inputs(inputs: &[&str]) -> Result<Self> {
+        ensure!(inputs.len() == 4, "Expected 4 ship inputs, got {}", inputs.len());
+        let mut bitboards = [0; 4];
+        for (slot, input) in bitboards.iter_mut().zip(inputs) {
+            *slot = parse_u64_literal(input)?;
+        }
+        Self::from_bitboards(bitboards)
+    }
+
+    /// Every cell covered by a ship, the `ships` entry of the board record.
+    pub fn occupied(&self) -> u64 {
+        self.bitboards.iter().fold(0, |all, bits| all | bits)
+    }
+
+    pub fn ship_at(&self, cell: Cell) -> Option<Ship> {
+        Ship::ALL.into_iter().zip(self.bitboards).find(|(_, bits)| bits & cell.bit() != 0).map(|(ship, _)| ship)
+    }
+}
+
+/// One player's grid: their fleet and the shots fired at it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Board {
+    pub fleet: Fleet,
+    pub shots: u64,
+}
+
+impl Board {
+    pub fn new(fleet: Fleet) -> Self {
+        Self { fleet, shots: 0 }
+    }
+
+    /// Records a shot at `cell`, returning whether it hit. Repeated shots are rejected.
+    pub fn fire(&mut self, cell: Cell) -> Result<bool> {
+        validate_shot(self.shots, cell.bit())?;
+        self.shots |= cell.bit();
+        Ok(self.fleet.occupied() & cell.bit() != 0)
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.shots & self.fleet.occupied()
+    }
+
+    pub fn is_sunk(&self, ship: Ship) -> bool {
+        let bits = self.fleet.bitboards()[ship.index()];
+        self.shots & bits == bits
+    }
+
+    pub fn all_sunk(&self) -> bool {
+        self.hits() == self.fleet.occupied()
+    }
+}
+
+/// Checks that `bits` is `ship` laid out in one straight, unbroken horizontal or vertical line.
+pub fn validate_ship(ship: Ship, bits: u64) -> Result<()> {
+    let length = ship.length();
+    ensure!(
+        bits.count_ones() == length as u32,
+        "{ship} covers {} cells, expected {length}",
+        bits.count_ones()
+    );
+    let origin = Cell::from_bit(1u64 << (63 - bits.leading_zeros()))?;
+    let horizontal = ship_bits(length, origin.row, origin.col, true);
+    let vertical = ship_bits(length, origin.row, origin.col, false);
+    ensure!(
+        horizontal.ok() == Some(bits) || vertical.ok() == Some(bits),
+        "{ship} must be one straight horizontal or vertical line"
+    );
+    Ok(())
+}
+
+/// Checks that `shot` is a single cell that is not in `played` yet.
+pub fn validate_shot(played: u64, shot: u64) -> Result<()> {
+    let cell = Cell::from_bit(shot)?;
+    ensure!(played & shot == 0, "Already fired at {cell}");
+    Ok(())
+}
+
+/// Parses a `u64` Aleo literal such as `12u64`, the suffix is optional.
+pub fn parse_u64_literal(input: &str) -> Result<u64> {
+    let input = input.trim();
+    Ok(input.strip_suffix("u64").unwrap_or(input).replace('_', "").parse()?)
+}
+
+/// The single bit encoding the cell at `row`, `col`.
+pub fn cell_bit(row: u8, col: u8) -> u64 {
+    1u64 << (63 - (row * SIZE + col))
+}
+
+/// Parses a cell such as `C4`: a row letter `A`-`H` followed by a column `1`-`8`.
+pub fn parse_cell(cell: &str) -> Result<(u8, u8)> {
+    let cell = cell.trim().to_ascii_uppercase();
+    let mut chars = cell.chars();
+    let (Some(row), Some(col), None) = (chars.next(), chars.next(), chars.next()) else {
+        bail!("Invalid cell {cell}");
+    };
+    ensure!(('A'..='H').contains(&row), "Row must be A-H, got {row}");
+    ensure!(('1'..='8').contains(&col), "Column must be 1-8, got {col}");
+    Ok((row as u8 - b'A', col as u8 - b'1'))
+}
+
+/// Bitboard of a ship of `length` cells starting at `row`, `col`.
+pub fn ship_bits(length: u8, row: u8, col: u8, horizontal: bool) -> Result<u64> {
+    let (end_row, end_col) = if horizontal { (row, col + length - 1) } else { (row + length - 1, col) };
+    ensure!(end_row < SIZE && end_col < SIZE, "Ship does not fit on the board");
+    Ok((0..length)
+        .map(|i| if horizontal { cell_bit(row, col + i) } else { cell_bit(row + i, col) })
+        .fold(0, |bits, bit| bits | bit))
+}
+
+/// Whether the cell at `row`, `col` is set in `bits`.
+pub fn is_set(bits: u64, row: u8, col: u8) -> bool {
+    bits & cell_bit(row, col) != 0
+}
+
+/// Every cell of `bits`, row by row.
+pub fn cells(bits: u64) -> impl Iterator<Item = Cell> {
+    Cell::all().filter(move |cell| bits & cell.bit() != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    /// Cases per property; case `n` runs on an RNG seeded with `n`, so a failure names the seed to reproduce it.
+    const CASES: u64 = 20_000;
+
+    fn seeded(seed: u64) -> StdRng {
+        StdRng::seed_from_u64(seed)
+    }
+
+    /// The rules, written against the grid rather than the bit helpers under test: every ship is
+    /// its length of cells in one row or column without a gap, and no two ships share a cell.
+    fn oracle(bitboards: [u64; 4]) -> bool {
+        let straight = |bits: u64, length: usize| {
+            // (row, col) of every set cell, row by row. Bit 63 is the top-left cell.
+            let cells = (0..64).filter(|i| bits >> (63 - i) & 1 == 1).map(|i| (i / 8, i % 8)).collect::<Vec<_>>();
+            let row = cells.windows(2).all(|pair| pair[1] == (pair[0].0, pair[0].1 + 1));
+            let column = cells.windows(2).all(|pair| pair[1] == (pair[0].0 + 1, pair[0].1));
+            cells.len() == length && (row || column)
+        };
+        let lengths = [5, 4, 3, 2];
+        let disjoint = (0..4).all(|a| (a + 1..4).all(|b| bitboards[a] & bitboards[b] == 0));
+        bitboards.iter().zip(lengths).all(|(bits, length)| straight(*bits, length)) && disjoint
+    }
+
+    /// `length` consecutive bit indices across or down from a random cell, wrapping over row ends
+    /// or running off the bottom as it comes, for the oracle to tell apart.
+    fn run<R: Rng>(rng: &mut R, length: u32) -> u64 {
+        let step = if rng.gen() { 1 } else { 8 };
+        let start = rng.gen_range(0..64);
+        (0..length).map(|k| start + k * step).filter(|i| *i < 64).fold(0, |bits, i| bits | 1 << (63 - i))
+    }
+
+    /// Any bitboard, biased towards near misses of a legal ship of `length` cells.
+    fn arbitrary_ship<R: Rng>(rng: &mut R, length: u32) -> u64 {
+        match rng.gen_range(0..6) {
+            0 => rng.gen(),
+            1 => (0..rng.gen_range(0..=6)).fold(0, |bits, _| bits | 1 << rng.gen_range(0..64)),
+            2 => {
+                let length = rng.gen_range(length - 1..=length + 1);
+                run(rng, length)
+            }
+            3 => run(rng, length) ^ 1 << rng.gen_range(0..64),
+            _ => run(rng, length),
+        }
+    }
+
+    fn arbitrary_bitboards<R: Rng>(rng: &mut R) -> [u64; 4] {
+        // Four plain runs are a legal fleet often enough to cover that side as well.
+        let runs = rng.gen_bool(0.5);
+        [5, 4, 3, 2].map(|length| if runs { run(rng, length) } else { arbitrary_ship(rng, length) })
+    }
+
+    fn placement(ship: Ship, cell: &str, orientation: Orientation) -> Placement {
+        Placement { ship, origin: cell.parse().unwrap(), orientation }
+    }
+
+    #[test]
+    fn top_left_cell_is_the_most_significant_bit() {
+        assert_eq!(Cell::from_str("A1").unwrap().bit(), 1 << 63);
+        assert_eq!(Cell::from_str("A8").unwrap().bit(), 1 << 56);
+        assert_eq!(Cell::from_str("H8").unwrap().bit(), 1);
+        assert_eq!(ship_bits(2, 0, 0, true).unwrap(), 0b11 << 62);
+    }
+
+    #[test]
+    fn cells_round_trip_through_bits() {
+        for cell in Cell::all() {
+            assert_eq!(Cell::from_bit(cell.bit()).unwrap(), cell);
+            assert_eq!(cell.to_string().parse::<Cell>().unwrap(), cell);
+            assert_eq!(cells(cell.bit()).collect::<Vec<_>>(), vec![cell]);
+        }
+        assert_eq!(Cell::all().fold(0, |bits, cell| bits | cell.bit()), u64::MAX);
+    }
+
+    #[test]
+    fn bitboards_are_accepted_exactly_when_the_rules_allow() {
+        let mut legal = 0;
+        for seed in 0..CASES {
+            let bitboards = arbitrary_bitboards(&mut seeded(seed));
+            let fleet = Fleet::from_bitboards(bitboards);
+            assert_eq!(fleet.is_ok(), oracle(bitboards), "seed {seed}: {bitboards:#018x?}");
+            let Ok(fleet) = fleet else { continue };
+            legal += 1;
+            assert_eq!(fleet.bitboards(), bitboards, "seed {seed}");
+            let inputs = fleet.to_inputs();
+            assert_eq!(Fleet::from_inputs(&inputs.each_ref().map(String::as_str)).unwrap(), fleet, "seed {seed}");
+            assert_eq!(fleet.occupied().count_ones(), 14, "seed {seed}");
+            for cell in Cell::all() {
+                let ship = Ship::ALL.into_iter().find(|ship| bitboards[ship.index()] & cell.bit() != 0);
+                assert_eq!(fleet.ship_at(cell), ship, "seed {seed}: {cell}");
+            }
+        }
+        assert!(legal > CASES / 100, "only {legal} legal fleets");
+    }
+
+    #[test]
+    fn random_fleets_are_legal() {
+        for seed in 0..1000 {
+            let fleet = Fleet::random(&mut seeded(seed));
+            assert!(oracle(fleet.bitboards()), "seed {seed}: {:#018x?}", fleet.bitboards());
+        }
+    }
+
+    #[test]
+    fn random_boards_sink_after_every_cell_is_fired_at() {
+        for seed in 0..100 {
+            let fleet = Fleet::random(&mut seeded(seed));
+            let mut board = Board::new(fleet);
+            for cell in Cell::all() {
+                assert_eq!(board.fire(cell).unwrap(), fleet.occupied() & cell.bit() != 0);
+            }
+            assert_eq!(board.shots, u64::MAX);
+            assert_eq!(board.hits(), fleet.occupied());
+            assert!(board.all_sunk() && Ship::ALL.iter().all(|ship| board.is_sunk(*ship)));
+        }
+    }
+
+    #[test]
+    fn placements_build_the_expected_fleet() {
+        let fleet = Fleet::new(&[
+            placement(Ship::Carrier, "A1", Orientation::Horizontal),
+            placement(Ship::Battleship, "B1", Orientation::Vertical),
+            placement(Ship::Cruiser, "H6", Orientation::Horizontal),
+            placement(Ship::Destroyer, "D8", Orientation::Vertical),
+        ])
+        .unwrap();
+        assert_eq!(fleet.ship_at("A5".parse().unwrap()), Some(Ship::Carrier));
+        assert_eq!(fleet.ship_at("E1".parse().unwrap()), Some(Ship::Battleship));
+        assert_eq!(fleet.ship_at("H8".parse().unwrap()), Some(Ship::Cruiser));
+        assert_eq!(fleet.ship_at("E8".parse().unwrap()), Some(Ship::Destroyer));
+        assert_eq!(fleet.ship_at("A6".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn every_straight_ship_is_valid() {
+        for ship in Ship::ALL {
+            for cell in Cell::all() {
+                for horizontal in [true, false] {
+                    if let Ok(bits) = ship_bits(ship.length(), cell.row, cell.col, horizontal) {
+                        validate_ship(ship, bits).unwrap();
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ships_off_the_board_are_rejected() {
+        assert!(placement(Ship::Carrier, "A5", Orientation::Horizontal).bits().is_err());
+        assert!(placement(Ship::Destroyer, "H1", Orientation::Vertical).bits().is_err());
+        // A8 and B1 are neighbouring bits, but not a straight line on the grid.
+        let wrapped = cell_bit(0, 7) | cell_bit(1, 0);
+        assert!(validate_ship(Ship::Destroyer, wrapped).is_err());
+    }
+
+    #[test]
+    fn ships_of_the_wrong_length_or_shape_are_rejected() {
+        let cruiser = ship_bits(3, 2, 2, true).unwrap();
+        assert!(validate_ship(Ship::Destroyer, cruiser).is_err());
+        assert!(validate_ship(Ship::Battleship, cruiser).is_err());
+        assert!(validate_ship(Ship::Destroyer, 0).is_err());
+        let gap = cell_bit(2, 2) | cell_bit(2, 4);
+        assert!(validate_ship(Ship::Destroyer, gap).is_err());
+        let diagonal = cell_bit(2, 2) | cell_bit(3, 3);
+        assert!(validate_ship(Ship::Destroyer, diagonal).is_err());
+        let bent = cell_bit(2, 2) | cell_bit(2, 3) | cell_bit(3, 3);
+        assert!(validate_ship(Ship::Cruiser, bent).is_err());
+    }
+
+    #[test]
+    fn overlapping_or_missing_ships_are_rejected() {
+        let overlapping = [
+            placement(Ship::Carrier, "A1", Orientation::Horizontal),
+            placement(Ship::Battleship, "A2", Orientation::Vertical),
+            placement(Ship::Cruiser, "H6", Orientation::Horizontal),
+            placement(Ship::Destroyer, "D8", Orientation::Vertical),
+        ];
+        assert!(Fleet::new(&overlapping).is_err());
+        assert!(Fleet::new(&overlapping[1..]).is_err());
+        let twice = [overlapping[0], overlapping[0], overlapping[2], overlapping[3]];
+        assert!(Fleet::new(&twice).is_err());
+        let bitboards = [ship_bits(5, 0, 0, true).unwrap(), ship_bits(4, 0, 3, false).unwrap(), 0b111, 0b11 << 8];
+        assert!(Fleet::from_bitboards(bitboards).is_err());
+    }
+
+    #[test]
+    fn shots_must_be_single_new_cells() {
+        let c4 = Cell::from_str("C4").unwrap().bit();
+        validate_shot(0, c4).unwrap();
+        assert!(validate_shot(c4, c4).is_err());
+        assert!(validate_shot(0, 0).is_err());
+        assert!(validate_shot(0, c4 | 1).is_err());
+        let mut board = Board::new(Fleet::random(&mut seeded(0)));
+        board.fire("C4".parse().unwrap()).unwrap();
+        assert!(board.fire("C4".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn shots_are_accepted_exactly_when_they_are_one_new_cell() {
+        for seed in 0..CASES {
+            let rng = &mut seeded(seed);
+            let played = rng.gen::<u64>() & rng.gen::<u64>();
+            let shot = if rng.gen() { 1 << rng.gen_range(0..64) } else { rng.gen::<u64>() >> rng.gen_range(0..64) };
+            let expected = shot.count_ones() == 1 && played & shot == 0;
+            assert_eq!(validate_shot(played, shot).is_ok(), expected, "seed {seed}: {played:#018x} {shot:#018x}");
+        }
+    }
+}
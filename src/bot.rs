@@ -0,0 +1,156 @@
+//! Headless player that drives whole games through the server, used for
+//! soak and load testing.
+
+use std::{collections::VecDeque, fmt, str::FromStr};
+
+use ::rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use anyhow::{anyhow, bail, Result};
+use snarkvm::prelude::{ConsensusStorage, Network, Request};
+use tracing::info;
+
+use crate::{
+    board::{cells, Board, Cell, Fleet},
+    client::{Action, GameConnection, GameEvent, GameState, SBClient},
+    protocol::ClientMessage,
+};
+
+/// How the bot picks its next shot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// Any cell it has not fired at yet.
+    Random,
+    /// Random cells on a checkerboard until a hit, then the neighbours of every hit.
+    HuntTarget,
+}
+
+impl FromStr for Strategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "random" => Ok(Strategy::Random),
+            "hunt" | "hunt-target" => Ok(Strategy::HuntTarget),
+            _ => bail!("Unknown strategy {s}, expected random or hunt"),
+        }
+    }
+}
+
+impl Strategy {
+    /// Picks the next shot from the cells already `played` and the `hits` among them.
+    pub fn next_shot<R: ::rand::Rng>(&self, played: u64, hits: u64, rng: &mut R) -> Option<Cell> {
+        let open = |cell: &Cell| played & cell.bit() == 0;
+        let candidates: Vec<Cell> = match self {
+            Strategy::Random => Cell::all().filter(open).collect(),
+            Strategy::HuntTarget => {
+                let targets = cells(hits).flat_map(|cell| cell.neighbours()).filter(open).collect::<Vec<_>>();
+                let hunt = || {
+                    let checkerboard = Cell::all().filter(|cell| (cell.row + cell.col) % 2 == 0).filter(open).collect::<Vec<_>>();
+                    if checkerboard.is_empty() {
+                        Cell::all().filter(open).collect()
+                    } else {
+                        checkerboard
+                    }
+                };
+                if targets.is_empty() {
+                    hunt()
+                } else {
+                    targets
+                }
+            }
+        };
+        candidates.choose(rng).copied()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Won,
+    Lost,
+    /// The game ended without a winner, e.g. the server shut down.
+    Aborted,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+pub struct Bot<N: Network, C: ConsensusStorage<N>> {
+    client: SBClient<N, C>,
+    strategy: Strategy,
+    rng: StdRng,
+}
+
+impl<N: Network, C: ConsensusStorage<N>> Bot<N, C> {
+    pub fn new(client: SBClient<N, C>, strategy: Strategy) -> Self {
+        Self { client, strategy, rng: StdRng::from_entropy() }
+    }
+
+    /// Queues for a game on `url` and plays it to the end.
+    pub async fn play_game(&mut self, url: &str) -> Result<Outcome> {
+        let address = self.client.address()?;
+        let mut connection = GameConnection::connect(url, self.client.private_key()).await?;
+        let mut state = GameState::default();
+        let fleet = Fleet::random(&mut self.rng);
+        let mut sent = false;
+        loop {
+            let event = connection.next_event().await?;
+            match &event {
+                GameEvent::Status { .. } | GameEvent::Error(_) | GameEvent::Rejected { .. } => sent = false,
+                GameEvent::Resigned(resigned) => {
+                    return Ok(if *resigned == address { Outcome::Lost } else { Outcome::Won });
+                }
+                GameEvent::Shutdown => return Ok(Outcome::Aborted),
+                _ => {}
+            }
+            state.apply(&event);
+
+            // The loser is the one who can see all of its ships sunk.
+            if (Board { fleet, shots: state.incoming }).all_sunk() {
+                connection.send(&ClientMessage::Resign).await?;
+                return Ok(Outcome::Lost);
+            }
+            if sent {
+                continue;
+            }
+            let Some(action) = state.pending_action() else {
+                continue;
+            };
+            let requests = tokio::task::block_in_place(|| self.authorize(action, &state, &fleet))?;
+            let Some(requests) = requests else {
+                return Ok(Outcome::Aborted);
+            };
+            info!("{} sends {:?}", address, action);
+            connection.send_move(requests).await?;
+            sent = true;
+        }
+    }
+
+    fn authorize(
+        &mut self,
+        action: Action,
+        state: &GameState<N>,
+        fleet: &Fleet,
+    ) -> Result<Option<VecDeque<Request<N>>>> {
+        let board = || state.board.clone().ok_or_else(|| anyhow!("No board record"));
+        let last_move = || state.last_move.clone().ok_or_else(|| anyhow!("No move record"));
+        let requests = match action {
+            Action::Initialize => {
+                let opponent = state.opponent.ok_or_else(|| anyhow!("No opponent"))?;
+                self.client.initialize_board(fleet, opponent)?
+            }
+            Action::Offer => self.client.offer_battleship(board()?)?,
+            Action::Start => self.client.start_battleship(board()?, last_move()?)?,
+            Action::Play => {
+                let played = state.board_entry("played_tiles");
+                let hits = state.board_entry("hits_and_misses");
+                let Some(shot) = self.strategy.next_shot(played, hits, &mut self.rng) else {
+                    return Ok(None);
+                };
+                self.client.play(board()?, last_move()?, shot)?
+            }
+        };
+        Ok(Some(requests))
+    }
+}
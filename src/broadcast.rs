@@ -0,0 +1,261 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, ensure, Result};
+use serde::Serialize;
+use snarkvm::prelude::{FromStr, Network, Transaction};
+use tracing::{info, warn};
+
+use crate::{metrics::METRICS, node::{NodeClient, NodeError}};
+
+/// How many peers have to accept a transaction for the broadcast to count as a success.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BroadcastStrategy {
+    /// Try the peers in order and stop at the first one that accepts.
+    #[default]
+    FirstSuccess,
+    /// Send to every peer, all of them have to accept.
+    All,
+    /// Send to every peer, this many have to accept.
+    Quorum(usize),
+}
+
+impl FromStr for BroadcastStrategy {
+    type Err = anyhow::Error;
+
+    /// `first-success`, `all` or `quorum:<n>`.
+    fn from_str(strategy: &str) -> Result<Self> {
+        match strategy.split_once(':') {
+            None if strategy == "first-success" => Ok(BroadcastStrategy::FirstSuccess),
+            None if strategy == "all" => Ok(BroadcastStrategy::All),
+            Some(("quorum", n)) => {
+                let n = n.parse()?;
+                ensure!(n > 0, "A quorum needs at least one peer");
+                Ok(BroadcastStrategy::Quorum(n))
+            }
+            _ => bail!("Broadcast strategy must be first-success, all or quorum:<n>"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BroadcastConfig {
+    /// Node URLs to broadcast to, the query nodes if empty.
+    pub peers: Vec<String>,
+    pub strategy: BroadcastStrategy,
+    /// A transaction the network does not know this long after the last attempt is sent again.
+    pub rebroadcast_after: Duration,
+    /// Attempts after which a transaction that never showed up is given up on.
+    pub max_attempts: u32,
+}
+
+impl Default for BroadcastConfig {
+    fn default() -> Self {
+        Self {
+            peers: vec![],
+            strategy: BroadcastStrategy::default(),
+            rebroadcast_after: Duration::from_secs(120),
+            max_attempts: 5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum PeerOutcome {
+    Accepted,
+    /// The peer refused the transaction, `reason` is its response body.
+    Rejected { status: u16, reason: String },
+    Unreachable { reason: String },
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PeerResult {
+    pub peer: String,
+    #[serde(flatten)]
+    pub outcome: PeerOutcome,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BroadcastStatus {
+    /// Accepted by enough peers, waiting to show up on the network.
+    Broadcast,
+    /// Not accepted by enough peers; it is retried after the rebroadcast timeout.
+    Failed,
+    /// The network knows the transaction.
+    Confirmed,
+    /// Still unknown to the network after the last attempt.
+    Abandoned,
+}
+
+/// What happened to one transaction, as served by `/api/broadcasts`.
+#[derive(Clone, Debug, Serialize)]
+#[serde(bound = "")]
+pub struct BroadcastRecord<N: Network> {
+    pub transaction_id: N::TransactionID,
+    pub status: BroadcastStatus,
+    pub attempts: u32,
+    pub first_attempt: u64,
+    pub last_attempt: u64,
+    /// The peer results of the last attempt.
+    pub peers: Vec<PeerResult>,
+}
+
+struct Entry<N: Network> {
+    record: BroadcastRecord<N>,
+    /// Kept until the network knows the transaction, for rebroadcasting.
+    transaction: Option<Transaction<N>>,
+    last_attempt: Instant,
+}
+
+/// Most transactions remembered; the oldest finished ones are forgotten first.
+const MAX_TRACKED: usize = 1024;
+
+/// Sends proven transactions to the network and keeps track of the outcome.
+#[derive(Clone)]
+pub struct Broadcaster<N: Network> {
+    node: NodeClient,
+    config: Arc<BroadcastConfig>,
+    entries: Arc<RwLock<HashMap<N::TransactionID, Entry<N>>>>,
+}
+
+impl<N: Network> Broadcaster<N> {
+    pub fn new(node: NodeClient, mut config: BroadcastConfig) -> Result<Self> {
+        if config.peers.is_empty() {
+            config.peers = node.endpoints().to_vec();
+        }
+        if let BroadcastStrategy::Quorum(n) = config.strategy {
+            ensure!(n <= config.peers.len(), "Quorum of {n} with only {} peers", config.peers.len());
+        }
+        Ok(Self { node, config: Arc::new(config), entries: Default::default() })
+    }
+
+    /// Broadcasts a new transaction and remembers it for rebroadcasting.
+    pub fn broadcast(&self, transaction: Transaction<N>) -> BroadcastRecord<N> {
+        let id = transaction.id();
+        let now = unix_time();
+        let (status, peers) = self.send(&transaction);
+        let record = BroadcastRecord { transaction_id: id, status, attempts: 1, first_attempt: now, last_attempt: now, peers };
+        let entry = Entry { record: record.clone(), transaction: Some(transaction), last_attempt: Instant::now() };
+        let mut entries = self.entries.write().unwrap();
+        entries.insert(id, entry);
+        prune(&mut entries);
+        record
+    }
+
+    /// Checks every transaction whose last attempt is older than the rebroadcast timeout:
+    /// confirmed ones are done, the others are sent again until they run out of attempts.
+    pub fn rebroadcast_due(&self) {
+        let due = {
+            let entries = self.entries.read().unwrap();
+            entries
+                .iter()
+                .filter(|(_, entry)| entry.transaction.is_some())
+                .filter(|(_, entry)| entry.last_attempt.elapsed() >= self.config.rebroadcast_after)
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>()
+        };
+        for id in due {
+            let seen = match self.node.transaction::<N>(&id) {
+                Ok(_) => true,
+                Err(err) if err.is_not_found() => false,
+                Err(err) => {
+                    warn!("check transaction {}: {}", id, err);
+                    continue;
+                }
+            };
+            let mut entries = self.entries.write().unwrap();
+            let Some(entry) = entries.get_mut(&id) else {
+                continue;
+            };
+            if seen {
+                entry.record.status = BroadcastStatus::Confirmed;
+                entry.transaction = None;
+                continue;
+            }
+            if entry.record.attempts >= self.config.max_attempts {
+                warn!("Giving up on transaction {} after {} attempts", id, entry.record.attempts);
+                entry.record.status = BroadcastStatus::Abandoned;
+                entry.transaction = None;
+                continue;
+            }
+            let Some(transaction) = entry.transaction.clone() else {
+                continue;
+            };
+            // Do not hold the lock while talking to the peers.
+            drop(entries);
+            info!("Rebroadcasting transaction {}", id);
+            let (status, peers) = self.send(&transaction);
+            if let Some(entry) = self.entries.write().unwrap().get_mut(&id) {
+                entry.record.status = status;
+                entry.record.peers = peers;
+                entry.record.attempts += 1;
+                entry.record.last_attempt = unix_time();
+                entry.last_attempt = Instant::now();
+            }
+        }
+    }
+
+    pub fn get(&self, id: &N::TransactionID) -> Option<BroadcastRecord<N>> {
+        self.entries.read().unwrap().get(id).map(|entry| entry.record.clone())
+    }
+
+    /// Every remembered transaction, newest first.
+    pub fn recent(&self) -> Vec<BroadcastRecord<N>> {
+        let mut records = self.entries.read().unwrap().values().map(|entry| entry.record.clone()).collect::<Vec<_>>();
+        records.sort_by_key(|record| std::cmp::Reverse(record.first_attempt));
+        records
+    }
+
+    fn send(&self, transaction: &Transaction<N>) -> (BroadcastStatus, Vec<PeerResult>) {
+        let mut peers = vec![];
+        for peer in &self.config.peers {
+            let outcome = match self.node.post_to::<N, N::TransactionID, _>(peer, "transaction/broadcast", transaction) {
+                Ok(_) => PeerOutcome::Accepted,
+                Err(NodeError::Rejected { status, message }) => PeerOutcome::Rejected { status, reason: message },
+                Err(err) => PeerOutcome::Unreachable { reason: err.to_string() },
+            };
+            if outcome != PeerOutcome::Accepted {
+                warn!("Broadcast of {} to {}: {:?}", transaction.id(), peer, outcome);
+            }
+            let accepted = outcome == PeerOutcome::Accepted;
+            peers.push(PeerResult { peer: peer.clone(), outcome });
+            if accepted && self.config.strategy == BroadcastStrategy::FirstSuccess {
+                break;
+            }
+        }
+        let accepted = peers.iter().filter(|result| result.outcome == PeerOutcome::Accepted).count();
+        let required = match self.config.strategy {
+            BroadcastStrategy::FirstSuccess => 1,
+            BroadcastStrategy::All => self.config.peers.len(),
+            BroadcastStrategy::Quorum(n) => n,
+        };
+        let status = if accepted >= required { BroadcastStatus::Broadcast } else { BroadcastStatus::Failed };
+        let label = if status == BroadcastStatus::Broadcast { "success" } else { "failure" };
+        METRICS.broadcasts.with_label_values(&[label]).inc();
+        (status, peers)
+    }
+}
+
+fn prune<N: Network>(entries: &mut HashMap<N::TransactionID, Entry<N>>) {
+    while entries.len() > MAX_TRACKED {
+        let oldest = entries
+            .iter()
+            .filter(|(_, entry)| entry.transaction.is_none())
+            .min_by_key(|(_, entry)| entry.record.first_attempt)
+            .map(|(id, _)| *id);
+        match oldest {
+            Some(id) => entries.remove(&id),
+            None => break,
+        };
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or_default()
+}
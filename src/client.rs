@@ -0,0 +1,405 @@
+//! Client library for the `/battleship` game server.
+//!
+//! [`SBClient`] authorizes `battleship.aleo` calls locally with the player's
+//! private key, [`GameConnection`] speaks the [`crate::protocol`] over a
+//! WebSocket, decrypting the player's records with their view key, and
+//! [`GameState`] keeps track of the player's own records.
+
+use std::{collections::VecDeque, str::FromStr};
+
+use ::rand::thread_rng;
+use anyhow::{bail, ensure, Result};
+use futures::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use snarkvm::prelude::{
+    Address, ConsensusStorage, ConsensusStore, Entry, Field, Identifier, Literal, Network, Plaintext, PrivateKey,
+    ProgramID, Record, Request, Value, ViewKey, VM,
+};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+use crate::{
+    board::{Cell, Fleet},
+    node::NodeClient,
+    protocol::{decode_player_message, sign_challenge, ClientMessage, PlayerMessage, SealedRecord, PROTOCOL_VERSION},
+    records::RecordScanner,
+    table::{ErrorCode, Status},
+    utils::vm_load_deployment,
+};
+
+pub const PROGRAM_ID: &str = "battleship.aleo";
+
+pub struct SBClient<N: Network, C: ConsensusStorage<N>> {
+    vm: VM<N, C>,
+    pk: PrivateKey<N>,
+}
+
+impl<N: Network, C: ConsensusStorage<N>> SBClient<N, C> {
+    pub fn new(c: C, pk: PrivateKey<N>) -> Result<SBClient<N, C>> {
+        let store = ConsensusStore::from(c);
+        let vm = VM::from(store)?;
+        let sb_client = Self { vm, pk };
+        Ok(sb_client)
+    }
+
+    /// Loads `battleship.aleo` and its imports from the node.
+    pub fn load_program(&self, node: &NodeClient) -> Result<()> {
+        vm_load_deployment(&self.vm, node, &ProgramID::from_str(PROGRAM_ID)?)
+    }
+
+    pub fn address(&self) -> Result<Address<N>> {
+        Address::try_from(&self.pk)
+    }
+
+    pub fn private_key(&self) -> &PrivateKey<N> {
+        &self.pk
+    }
+
+    pub fn view_key(&self) -> Result<ViewKey<N>> {
+        ViewKey::try_from(&self.pk)
+    }
+
+    /// A scanner for this player's records on chain, in case a status message was missed.
+    pub fn record_scanner(&self) -> Result<RecordScanner<N>> {
+        RecordScanner::new(self.pk)
+    }
+
+    pub fn request_transfer(
+        &self,
+        to: Address<N>,
+        amount: u64,
+        record: Record<N, Plaintext<N>>,
+    ) -> Result<Request<N>> {
+        let rng = &mut thread_rng();
+        let inputs = [
+            Value::Record(record),
+            Value::from_str(&format!("{to}"))?,
+            Value::from_str(&format!("{amount}u64"))?,
+        ];
+        let authorization = self
+            .vm
+            .authorize(&self.pk, "credits.aleo", "transfer", inputs, rng)?;
+        authorization.next()
+    }
+
+    pub fn request_fee(&self, fee_record: Record<N, Plaintext<N>>, fee: u64) -> Result<Request<N>> {
+        let rng = &mut thread_rng();
+        let inputs = [
+            Value::Record(fee_record),
+            Value::from_str(&format!("{fee}u64"))?,
+        ];
+        let authorization = self
+            .vm
+            .authorize(&self.pk, "credits.aleo", "fee", inputs, rng)?;
+        authorization.next()
+    }
+
+    fn authorize(&self, function: &str, inputs: Vec<Value<N>>) -> Result<VecDeque<Request<N>>> {
+        let rng = &mut thread_rng();
+        let authorization = self.vm.authorize(&self.pk, PROGRAM_ID, function, inputs, rng)?;
+        Ok(authorization.to_vec_deque())
+    }
+
+    /// Places `fleet` for a game against `opponent`.
+    pub fn initialize_board(&self, fleet: &Fleet, opponent: Address<N>) -> Result<VecDeque<Request<N>>> {
+        let mut inputs = fleet
+            .to_inputs()
+            .iter()
+            .map(|ship| Value::from_str(ship))
+            .collect::<Result<Vec<_>>>()?;
+        inputs.push(Value::from_str(&opponent.to_string())?);
+        self.authorize("initialize_board", inputs)
+    }
+
+    pub fn offer_battleship(&self, board: Record<N, Plaintext<N>>) -> Result<VecDeque<Request<N>>> {
+        self.authorize("offer_battleship", vec![Value::Record(board)])
+    }
+
+    pub fn start_battleship(
+        &self,
+        board: Record<N, Plaintext<N>>,
+        move_start: Record<N, Plaintext<N>>,
+    ) -> Result<VecDeque<Request<N>>> {
+        self.authorize("start_battleship", vec![Value::Record(board), Value::Record(move_start)])
+    }
+
+    /// Fires at `shot`.
+    pub fn play(
+        &self,
+        board: Record<N, Plaintext<N>>,
+        move_incoming: Record<N, Plaintext<N>>,
+        shot: Cell,
+    ) -> Result<VecDeque<Request<N>>> {
+        self.authorize(
+            "play",
+            vec![Value::Record(board), Value::Record(move_incoming), Value::from_str(&format!("{}u64", shot.bit()))?],
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Seat {
+    A,
+    B,
+}
+
+/// A server message, parsed into the client's view of the game.
+#[derive(Clone, Debug)]
+pub enum GameEvent<N: Network> {
+    Started { seat: Seat, opponent: Address<N> },
+    GameId(u64),
+    /// `records` are the player's new records in the clear, `commitments` the ones the server only
+    /// sent as commitments; those have to be looked up on chain once the move is confirmed.
+    Status { status: Status, records: Vec<Record<N, Plaintext<N>>>, commitments: Vec<Field<N>> },
+    Transaction(N::TransactionID),
+    Pong(u64),
+    Chat { from: Address<N>, text: String },
+    Resigned(Address<N>),
+    OpponentDisconnected,
+    OpponentReconnected,
+    Error(String),
+    Rejected { code: ErrorCode, message: String },
+    Shutdown,
+}
+
+impl<N: Network> GameEvent<N> {
+    /// Parses a server message, decrypting encrypted records with `view_key`.
+    pub fn from_message(message: PlayerMessage<N>, view_key: &ViewKey<N>) -> Result<Self> {
+        let event = match message {
+            PlayerMessage::Start(seat, opponent) => {
+                let seat = match seat.as_str() {
+                    "A" => Seat::A,
+                    "B" => Seat::B,
+                    _ => bail!("Unknown seat {seat}"),
+                };
+                GameEvent::Started { seat, opponent }
+            }
+            PlayerMessage::GameId(id) => GameEvent::GameId(id),
+            PlayerMessage::GameStatus(status, sealed) => {
+                let (mut records, mut commitments) = (vec![], vec![]);
+                for record in sealed {
+                    match record {
+                        SealedRecord::Plaintext(record) => records.push(record),
+                        SealedRecord::Ciphertext(record) => records.push(record.decrypt(view_key)?),
+                        SealedRecord::Commitment(commitment) => commitments.push(commitment),
+                    }
+                }
+                GameEvent::Status { status, records, commitments }
+            }
+            PlayerMessage::TxID(id) => GameEvent::Transaction(id),
+            PlayerMessage::Pong(nonce) => GameEvent::Pong(nonce),
+            PlayerMessage::Chat(from, text) => GameEvent::Chat { from, text },
+            PlayerMessage::Resigned(address) => GameEvent::Resigned(address),
+            PlayerMessage::Disconnected(_) => GameEvent::OpponentDisconnected,
+            PlayerMessage::Reconnected(_) => GameEvent::OpponentReconnected,
+            PlayerMessage::Error(err) => GameEvent::Error(err),
+            PlayerMessage::Rejected(code, message) => GameEvent::Rejected { code, message },
+            PlayerMessage::Shutdown => GameEvent::Shutdown,
+            PlayerMessage::Welcome(version) => bail!("Unexpected welcome for version {version}"),
+            PlayerMessage::Challenge(_) => bail!("Unexpected challenge outside of the handshake"),
+        };
+        Ok(event)
+    }
+}
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// A signed in connection to the `/battleship` WebSocket.
+pub struct GameConnection<N: Network> {
+    address: Address<N>,
+    view_key: ViewKey<N>,
+    sink: SplitSink<Socket, Message>,
+    stream: SplitStream<Socket>,
+}
+
+impl<N: Network> GameConnection<N> {
+    /// Connects to `url` (e.g. `ws://127.0.0.1:3000/battleship`) and queues the key's address for a game.
+    pub async fn connect(url: &str, private_key: &PrivateKey<N>) -> Result<Self> {
+        let address = Address::try_from(private_key)?;
+        Self::handshake(url, ClientMessage::Hello { version: PROTOCOL_VERSION, address }, private_key).await
+    }
+
+    /// Takes over the seat of the key's address in `game_id` after a dropped connection.
+    pub async fn reconnect(url: &str, private_key: &PrivateKey<N>, game_id: u64) -> Result<Self> {
+        let hello = ClientMessage::Reconnect { version: PROTOCOL_VERSION, address: Address::try_from(private_key)?, game_id };
+        Self::handshake(url, hello, private_key).await
+    }
+
+    /// Sends `hello`, signs the server's challenge with `private_key` and waits for the welcome.
+    async fn handshake(url: &str, hello: ClientMessage<N>, private_key: &PrivateKey<N>) -> Result<Self> {
+        let (socket, _) = connect_async(url).await?;
+        let (sink, stream) = socket.split();
+        let view_key = ViewKey::try_from(private_key)?;
+        let mut connection = Self { address: view_key.to_address(), view_key, sink, stream };
+        connection.send(&hello).await?;
+        loop {
+            match connection.recv().await? {
+                PlayerMessage::Challenge(challenge) => {
+                    let signature = sign_challenge(private_key, &challenge)?;
+                    connection.send(&ClientMessage::Prove { signature }).await?;
+                }
+                PlayerMessage::Welcome(version) => {
+                    ensure!(version == PROTOCOL_VERSION, "Server speaks version {version}");
+                    return Ok(connection);
+                }
+                PlayerMessage::Error(err) => bail!("Handshake rejected: {err}"),
+                message => bail!("Unexpected handshake reply: {message:?}"),
+            }
+        }
+    }
+
+    pub fn address(&self) -> &Address<N> {
+        &self.address
+    }
+
+    pub async fn send(&mut self, message: &ClientMessage<N>) -> Result<()> {
+        self.sink.send(Message::Text(serde_json::to_string(message)?)).await?;
+        Ok(())
+    }
+
+    pub async fn send_move(&mut self, requests: VecDeque<Request<N>>) -> Result<()> {
+        self.send(&ClientMessage::Move { requests }).await
+    }
+
+    async fn recv(&mut self) -> Result<PlayerMessage<N>> {
+        loop {
+            match self.stream.next().await.transpose()? {
+                Some(Message::Text(text)) => return Ok(decode_player_message(&text)?.1),
+                Some(Message::Ping(payload)) => self.sink.send(Message::Pong(payload)).await?,
+                Some(Message::Close(_)) | None => bail!("Disconnect"),
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// Waits for the next server message.
+    pub async fn next_event(&mut self) -> Result<GameEvent<N>> {
+        GameEvent::from_message(self.recv().await?, &self.view_key)
+    }
+}
+
+/// The player's own view of a game, built from [`GameEvent`]s.
+#[derive(Clone, Debug)]
+pub struct GameState<N: Network> {
+    pub seat: Option<Seat>,
+    pub game_id: Option<u64>,
+    pub opponent: Option<Address<N>>,
+    pub status: Option<Status>,
+    /// The latest `board_state` record owned by the player.
+    pub board: Option<Record<N, Plaintext<N>>>,
+    /// The latest `move` record owned by the player, needed for the next call.
+    pub last_move: Option<Record<N, Plaintext<N>>>,
+    pub transactions: Vec<N::TransactionID>,
+    /// Commitments of records the server did not send, see [`GameState::recover`].
+    pub commitments: Vec<Field<N>>,
+    /// Bitboard of every shot the opponent fired at this player.
+    pub incoming: u64,
+}
+
+/// The battleship call the server expects from this player next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Initialize,
+    Offer,
+    Start,
+    Play,
+}
+
+impl<N: Network> Default for GameState<N> {
+    fn default() -> Self {
+        Self {
+            seat: None,
+            game_id: None,
+            opponent: None,
+            status: None,
+            board: None,
+            last_move: None,
+            transactions: vec![],
+            commitments: vec![],
+            incoming: 0,
+        }
+    }
+}
+
+impl<N: Network> GameState<N> {
+    pub fn apply(&mut self, event: &GameEvent<N>) {
+        match event {
+            GameEvent::Started { seat, opponent } => {
+                self.seat = Some(*seat);
+                self.opponent = Some(*opponent);
+            }
+            GameEvent::GameId(id) => self.game_id = Some(*id),
+            GameEvent::Status { status, records, commitments } => {
+                self.status = Some(status.clone());
+                for record in records {
+                    self.take_record(record.clone());
+                }
+                self.commitments.extend(commitments);
+            }
+            GameEvent::Transaction(id) => self.transactions.push(*id),
+            _ => {}
+        }
+    }
+
+    /// Whether it is this player's turn to call `play`.
+    pub fn my_turn(&self) -> bool {
+        self.pending_action() == Some(Action::Play)
+    }
+
+    /// The call this player has to make next, if it is their turn.
+    pub fn pending_action(&self) -> Option<Action> {
+        let action = match (self.seat?, self.status.as_ref()) {
+            (Seat::A, None | Some(Status::AInitialize)) => Action::Initialize,
+            (Seat::B, Some(Status::BInitialize)) => Action::Initialize,
+            (Seat::A, Some(Status::AOffer)) => Action::Offer,
+            (Seat::B, Some(Status::BStart)) => Action::Start,
+            (Seat::A, Some(Status::ATurn)) | (Seat::B, Some(Status::BTurn)) => Action::Play,
+            _ => return None,
+        };
+        Some(action)
+    }
+
+    fn take_record(&mut self, record: Record<N, Plaintext<N>>) {
+        if is_board(&record) {
+            self.board = Some(record);
+        } else {
+            self.incoming |= u64_entry(&record, "incoming_fire_coordinate").unwrap_or_default();
+            self.last_move = Some(record);
+        }
+    }
+
+    /// Replaces the board and move records with the newest unspent ones the scanner found on chain,
+    /// including those the server only sent as commitments.
+    /// Moves that are not confirmed yet are not on chain, so this can only go back to the last confirmed move.
+    pub fn recover(&mut self, scanner: &RecordScanner<N>) -> Result<()> {
+        let program_id = ProgramID::from_str(PROGRAM_ID)?;
+        for record in scanner.unspent_for(&program_id) {
+            self.take_record(record);
+        }
+        self.commitments.retain(|commitment| !scanner.contains(commitment));
+        Ok(())
+    }
+
+    /// A `u64` entry of the player's board record, e.g. `ships` or `played_tiles`.
+    pub fn board_entry(&self, name: &str) -> u64 {
+        self.board.as_ref().and_then(|board| u64_entry(board, name).ok()).unwrap_or_default()
+    }
+}
+
+/// `board_state` records carry the `ships` entry, `move` records do not.
+pub fn is_board<N: Network>(record: &Record<N, Plaintext<N>>) -> bool {
+    Identifier::from_str("ships").is_ok_and(|ships| record.data().contains_key(&ships))
+}
+
+/// Reads a `u64` entry of a plaintext record.
+pub fn u64_entry<N: Network>(record: &Record<N, Plaintext<N>>, name: &str) -> Result<u64> {
+    let entry = record.find(&[Identifier::from_str(name)?])?;
+    match entry {
+        Entry::Constant(Plaintext::Literal(Literal::U64(value), _))
+        | Entry::Public(Plaintext::Literal(Literal::U64(value), _))
+        | Entry::Private(Plaintext::Literal(Literal::U64(value), _)) => Ok(*value),
+        _ => bail!("Entry {name} is not a u64"),
+    }
+}
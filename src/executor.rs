@@ -1,29 +1,46 @@
 use ::rand::thread_rng;
 use snarkvm::prelude::*;
-use tracing::info;
+use tracing::{info, warn};
 
-use crate::utils::vm_load_deployment;
-use std::collections::VecDeque;
+use crate::{
+    node::{NodeClient, QueryProxy},
+    protocol::{RecordMode, SealedRecord},
+    records::record_randomizer,
+    utils::{vm_load_deployment, vm_load_program_dir, vm_prewarm_keys},
+};
+use std::{collections::VecDeque, path::Path, sync::Arc};
 
 
 #[derive(Clone)]
 pub struct Executor<N: Network, C: ConsensusStorage<N>> {
     vm: VM<N, C>,
-    query: Query<N, C::BlockStorage>,
+    node: NodeClient,
+    query: Arc<QueryProxy>,
 }
 
 impl<N: Network, C: ConsensusStorage<N>> Executor<N, C> {
-    pub fn new(c: C, query: Query<N, C::BlockStorage>) -> Result<Self> {
+    pub fn new(c: C, node: NodeClient) -> Result<Self> {
         let store = ConsensusStore::from(c);
         let vm = VM::from(store)?;
-        match &query {
-            Query::VM(_) => todo!(),
-            Query::REST(url) => {
-                vm_load_deployment(&vm, url, &ProgramID::from_str("battleship.aleo")?)?;
-                info!("vm load deployment");
-            },
-        }
-        Ok(Self { vm, query })
+        vm_load_deployment(&vm, &node, &ProgramID::from_str("battleship.aleo")?)?;
+        info!("vm load deployment");
+        let query = Arc::new(QueryProxy::start::<N>(node.clone())?);
+        Ok(Self { vm, node, query })
+    }
+
+    /// Loads `battleship.aleo` and its imports from `dir` instead of the node, enough to evaluate moves.
+    pub fn from_program_dir(c: C, node: NodeClient, dir: &Path) -> Result<Self> {
+        let store = ConsensusStore::from(c);
+        let vm = VM::from(store)?;
+        vm_load_program_dir(&vm, dir, &ProgramID::from_str("battleship.aleo")?)?;
+        info!("vm load program from {}", dir.display());
+        let query = Arc::new(QueryProxy::start::<N>(node.clone())?);
+        Ok(Self { vm, node, query })
+    }
+
+    /// Loads or synthesizes the keys of every battleship function up front, see [`vm_prewarm_keys`].
+    pub fn prewarm<A: snarkvm::circuit::Aleo<Network = N>>(&self, cache_dir: Option<&Path>) -> Result<()> {
+        vm_prewarm_keys::<N, C, A>(&self.vm, &ProgramID::from_str("battleship.aleo")?, cache_dir)
     }
 
     pub fn evaluate<A: snarkvm::circuit::Aleo<Network = N>>(
@@ -34,18 +51,69 @@ impl<N: Network, C: ConsensusStorage<N>> Executor<N, C> {
         self.vm.process().read().evaluate::<A>(authorization)
     }
     
+    /// The records of `response` owned by `owner`, in the form `mode` sends them.
+    pub fn seal_records(
+        &self,
+        mode: RecordMode,
+        requests: &[Request<N>],
+        response: &Response<N>,
+        owner: &Address<N>,
+    ) -> Result<Vec<SealedRecord<N>>> {
+        let request = requests.first().ok_or_else(|| anyhow!("No requests"))?;
+        let process = self.vm.process();
+        let process = process.read();
+        let function = process.get_stack(request.program_id())?.get_function(request.function_name())?;
+        ensure!(function.outputs().len() == response.outputs().len(), "Response does not match {}", function.name());
+        // The response holds the outputs of the called function, in the order it declares them.
+        let mut sealed = vec![];
+        for (output, value) in function.outputs().iter().zip(response.outputs()) {
+            let Value::Record(record) = value else {
+                continue;
+            };
+            if **record.owner() != *owner {
+                continue;
+            }
+            sealed.push(match mode {
+                RecordMode::Plaintext => SealedRecord::Plaintext(record.clone()),
+                RecordMode::Encrypted => SealedRecord::Ciphertext(encrypt_record(&process, requests, record)?),
+                RecordMode::Commitment => SealedRecord::Commitment(match output.value_type() {
+                    ValueType::Record(name) => record.to_commitment(request.program_id(), name)?,
+                    ValueType::ExternalRecord(locator) => record.to_commitment(locator.program_id(), locator.resource())?,
+                    value_type => bail!("Output {value_type} is not a record"),
+                }),
+            });
+        }
+        Ok(sealed)
+    }
+
     pub fn execute_no_fee(&self, authorization: Authorization<N>) -> Result<Transaction<N>>{
         let rng = &mut thread_rng();
-        let (_response, execution, _metrics) = self.vm.execute_authorization_raw(authorization, Some(self.query.clone()), rng)?;
+        let query = Query::from(self.query.url());
+        let (_response, execution, _metrics) = self.vm.execute_authorization_raw(authorization, Some(query), rng)?;
         Transaction::from_execution(execution, None)
     }
 
-    pub fn broadcast(&self, transaction: &Transaction<N>) -> Result<()> {
-        match &self.query {
-            Query::VM(_) => todo!(),
-            Query::REST(url) => {
-                let url = format!("{url}/testnet3/transaction/broadcast");
-                ureq::post(&url).send_json(transaction)?;
+    /// Checks a fee-less execution the way a node would before it is broadcast: its id, duplicate
+    /// outputs, size, the proof against the loaded deployments and, through the node, that none of
+    /// its input records has been spent in the meantime.
+    pub fn verify(&self, transaction: &Transaction<N>) -> Result<()> {
+        ensure!(*transaction.id() == transaction.to_root()?, "Incorrect transaction ID {}", transaction.id());
+        ensure!(!has_duplicates(transaction.transition_ids()), "Duplicate transitions");
+        ensure!(!has_duplicates(transaction.transition_public_keys()), "Duplicate transition public keys");
+        ensure!(!has_duplicates(transaction.serial_numbers()), "Duplicate serial numbers");
+        ensure!(!has_duplicates(transaction.commitments()), "Duplicate commitments");
+        ensure!(!has_duplicates(transaction.nonces()), "Duplicate nonces");
+        let Transaction::Execute(_, execution, _) = transaction else {
+            bail!("Transaction {} is not an execution", transaction.id());
+        };
+        Transaction::check_execution_size(execution)?;
+        self.vm.process().read().verify_execution::<true>(execution)?;
+        for serial_number in transaction.serial_numbers() {
+            match self.node.get::<N, N::TransitionID>(&format!("find/transitionID/{serial_number}")) {
+                Ok(transition_id) => bail!("Input record {serial_number} was already spent by {transition_id}"),
+                Err(err) if err.is_not_found() => {}
+                // The proof checks out, an unreachable node is no reason to hold the transaction back.
+                Err(err) => warn!("check serial number {}: {}", serial_number, err),
             }
         }
         Ok(())
@@ -63,6 +131,29 @@ impl<N: Network, C: ConsensusStorage<N>> Executor<N, C> {
     // }
 }
 
+/// Encrypts a record created by `requests` to its owner, exactly as it is stored on chain. A record
+/// may come from a nested call, so the record outputs of every request are tried for its nonce.
+fn encrypt_record<N: Network>(
+    process: &Process<N>,
+    requests: &[Request<N>],
+    record: &Record<N, Plaintext<N>>,
+) -> Result<Record<N, Ciphertext<N>>> {
+    for request in requests {
+        let function = process.get_stack(request.program_id())?.get_function(request.function_name())?;
+        for output in function.outputs() {
+            let (ValueType::Record(_), Operand::Register(register)) = (output.value_type(), output.operand()) else {
+                continue;
+            };
+            let randomizer = record_randomizer(request, register)?;
+            if N::g_scalar_multiply(&randomizer) == *record.nonce() {
+                return record.encrypt(randomizer);
+            }
+        }
+    }
+    bail!("Record was not created by these requests")
+}
+
+#[allow(dead_code)]
 pub fn fee_from_execution<N: Network>(execution: Execution<N>) -> Result<Fee<N>> {
     ensure!(execution.len() == 1);
     let transition = execution.peek()?.clone();
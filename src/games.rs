@@ -0,0 +1,216 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use snarkvm::prelude::*;
+
+use crate::table::Status;
+
+/// Most finished games remembered; the oldest are forgotten first, along with their transactions.
+const MAX_FINISHED: usize = 1024;
+
+/// Shared view of every game the server has started, used by the REST API.
+#[derive(Clone)]
+pub struct GameRegistry<N: Network> {
+    inner: Arc<RwLock<Games<N>>>,
+}
+
+struct Games<N: Network> {
+    next_id: u64,
+    games: HashMap<u64, GameInfo<N>>,
+    transactions: HashMap<N::TransactionID, (u64, usize)>,
+    /// Finished games, oldest first.
+    finished: VecDeque<u64>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct GameInfo<N: Network> {
+    pub id: u64,
+    pub player_a: Address<N>,
+    pub player_b: Address<N>,
+    pub status: Status,
+    pub active: bool,
+    pub started_at: u64,
+    pub moves: Vec<MoveInfo<N>>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct MoveInfo<N: Network> {
+    pub function: String,
+    pub caller: Address<N>,
+    pub transaction_id: Option<N::TransactionID>,
+    /// Why the move was never broadcast, if proving or verifying it failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct GameSummary<N: Network> {
+    pub id: u64,
+    pub player_a: Address<N>,
+    pub player_b: Address<N>,
+    pub status: Status,
+}
+
+#[derive(Serialize)]
+pub struct MoveLookup<N: Network> {
+    pub game_id: u64,
+    pub index: usize,
+    #[serde(flatten)]
+    pub info: MoveInfo<N>,
+}
+
+impl<N: Network> Default for GameRegistry<N> {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(Games {
+                next_id: 0,
+                games: HashMap::new(),
+                transactions: HashMap::new(),
+                finished: VecDeque::new(),
+            })),
+        }
+    }
+}
+
+impl<N: Network> GameRegistry<N> {
+    pub fn create(&self, player_a: Address<N>, player_b: Address<N>) -> u64 {
+        let mut games = self.inner.write().unwrap();
+        let id = games.next_id;
+        games.next_id += 1;
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        games.games.insert(
+            id,
+            GameInfo {
+                id,
+                player_a,
+                player_b,
+                status: Status::AInitialize,
+                active: true,
+                started_at,
+                moves: vec![],
+            },
+        );
+        id
+    }
+
+    /// Records an accepted move and returns its index in the game history.
+    pub fn record_move(&self, id: u64, request: &Request<N>, status: Status) -> usize {
+        let mut games = self.inner.write().unwrap();
+        let Some(game) = games.games.get_mut(&id) else {
+            return 0;
+        };
+        game.status = status;
+        game.moves.push(MoveInfo {
+            function: request.function_name().to_string(),
+            caller: *request.caller(),
+            transaction_id: None,
+            error: None,
+        });
+        game.moves.len() - 1
+    }
+
+    pub fn record_transaction(&self, id: u64, index: usize, transaction_id: N::TransactionID) {
+        let mut games = self.inner.write().unwrap();
+        if let Some(info) = games.games.get_mut(&id).and_then(|game| game.moves.get_mut(index)) {
+            info.transaction_id = Some(transaction_id);
+            games.transactions.insert(transaction_id, (id, index));
+        }
+    }
+
+    pub fn record_failure(&self, id: u64, index: usize, error: String) {
+        let mut games = self.inner.write().unwrap();
+        if let Some(info) = games.games.get_mut(&id).and_then(|game| game.moves.get_mut(index)) {
+            info.error = Some(error);
+        }
+    }
+
+    pub fn finish(&self, id: u64) {
+        let mut games = self.inner.write().unwrap();
+        match games.games.get_mut(&id) {
+            Some(game) if game.active => game.active = false,
+            _ => return,
+        }
+        games.finished.push_back(id);
+        while games.finished.len() > MAX_FINISHED {
+            let Some(oldest) = games.finished.pop_front() else { break };
+            if let Some(game) = games.games.remove(&oldest) {
+                for transaction_id in game.moves.iter().filter_map(|info| info.transaction_id) {
+                    games.transactions.remove(&transaction_id);
+                }
+            }
+        }
+    }
+
+    pub fn active(&self) -> Vec<GameSummary<N>> {
+        let games = self.inner.read().unwrap();
+        let mut active = games
+            .games
+            .values()
+            .filter(|game| game.active)
+            .map(|game| GameSummary {
+                id: game.id,
+                player_a: game.player_a,
+                player_b: game.player_b,
+                status: game.status.clone(),
+            })
+            .collect::<Vec<_>>();
+        active.sort_by_key(|game| game.id);
+        active
+    }
+
+    pub fn get(&self, id: u64) -> Option<GameInfo<N>> {
+        self.inner.read().unwrap().games.get(&id).cloned()
+    }
+
+    pub fn find_transaction(&self, transaction_id: &N::TransactionID) -> Option<MoveLookup<N>> {
+        let games = self.inner.read().unwrap();
+        let (game_id, index) = *games.transactions.get(transaction_id)?;
+        let info = games.games.get(&game_id)?.moves.get(index)?.clone();
+        Some(MoveLookup { game_id, index, info })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type N = Testnet3;
+
+    fn address() -> Address<N> {
+        Address::try_from(PrivateKey::<N>::new(&mut ::rand::thread_rng()).unwrap()).unwrap()
+    }
+
+    fn request() -> Request<N> {
+        let private_key = PrivateKey::new(&mut ::rand::thread_rng()).unwrap();
+        let program_id = ProgramID::from_str("battleship.aleo").unwrap();
+        let function = Identifier::from_str("play").unwrap();
+        Request::sign(&private_key, program_id, function, std::iter::empty::<Value<N>>(), &[], &mut ::rand::thread_rng()).unwrap()
+    }
+
+    #[test]
+    fn the_oldest_finished_games_are_forgotten() {
+        let registry = GameRegistry::<N>::default();
+        let (a, b) = (address(), address());
+        let running = registry.create(a, b);
+        let first = registry.create(a, b);
+        let index = registry.record_move(first, &request(), Status::BInitialize);
+        let transaction_id = Field::<N>::rand(&mut ::rand::thread_rng()).into();
+        registry.record_transaction(first, index, transaction_id);
+        registry.finish(first);
+        for _ in 0..MAX_FINISHED {
+            let id = registry.create(a, b);
+            registry.finish(id);
+        }
+        assert!(registry.get(first).is_none());
+        assert!(registry.find_transaction(&transaction_id).is_none());
+        assert!(registry.get(first + 1).is_some());
+        assert!(registry.get(running).is_some_and(|game| game.active));
+        assert_eq!(registry.inner.read().unwrap().games.len(), MAX_FINISHED + 1);
+    }
+}
@@ -1,28 +1,164 @@
 
-use std::{sync::mpsc::{Sender, self}, time::Duration, thread::sleep};
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    thread::{sleep, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use snarkvm::{synthesizer::{Authorization, Transaction, ConsensusStorage}, prelude::Network};
+use anyhow::Result;
+use snarkvm::{synthesizer::{Authorization, ConsensusStorage}, prelude::{Network, Request}};
 use tokio::sync::oneshot;
+use tracing::{error, info, warn};
 
-use crate::executor::Executor;
-pub type ProofRequest<N> = (Authorization<N>, oneshot::Sender<Transaction<N>>);
-
-pub fn start_generator<N: Network, C: ConsensusStorage<N>>(executor: Executor<N,C>) -> Sender<ProofRequest<N>> {
-    let (tx, rx) = mpsc::channel::<ProofRequest<N>>();
-    std::thread::spawn(move|| {
-        while let Ok((authorization, notify)) = rx.recv() {
-            match executor.execute_no_fee(authorization) {
-                Ok(transaction) => {
-                    if let Err(err) = notify.send(transaction) {
-                        println!("Notify channel broken: {}", err);
-                    }
-                },
-                Err(err) => {
-                    println!("Execute error: {}", err);
-                }
+use crate::{broadcast::{BroadcastStatus, Broadcaster}, executor::Executor, metrics::METRICS, prover::{Proven, Prover}, queue::{ProofJob, ProofQueue}};
+
+/// The id of the broadcast transaction, or why the move could not be proven or did not verify.
+pub type ProofResult<N> = Result<<N as Network>::TransactionID>;
+
+/// Set once the server starts shutting down. Requests still queued after the
+/// deadline are persisted to the pending directory instead of being proven.
+pub type Deadline = Arc<OnceLock<Instant>>;
+
+const THROTTLE: Duration = Duration::from_secs(30);
+
+/// Proves queued authorizations on as many threads as the prover can take at once.
+/// `executor` verifies every transaction before it is broadcast.
+pub fn start_generator<N: Network, C: ConsensusStorage<N>>(
+    queue: ProofQueue<N>,
+    prover: Box<dyn Prover<N>>,
+    executor: Executor<N, C>,
+    broadcaster: Broadcaster<N>,
+    deadline: Deadline,
+    // Requests still queued at the deadline are dropped without one.
+    pending_dir: Option<PathBuf>,
+) -> JoinHandle<()> {
+    let prover: Arc<dyn Prover<N>> = prover.into();
+    std::thread::spawn(move || {
+        let workers = (0..prover.parallelism().max(1))
+            .map(|_| {
+                let (queue, prover, executor, broadcaster) = (queue.clone(), prover.clone(), executor.clone(), broadcaster.clone());
+                let (deadline, pending_dir) = (deadline.clone(), pending_dir.clone());
+                std::thread::spawn(move || {
+                    prove_queued(&queue, &*prover, &executor, &broadcaster, &deadline, pending_dir.as_deref())
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            if worker.join().is_err() {
+                error!("proof generator thread panicked");
+            }
+        }
+    })
+}
+
+fn prove_queued<N: Network, C: ConsensusStorage<N>>(
+    queue: &ProofQueue<N>,
+    prover: &dyn Prover<N>,
+    executor: &Executor<N, C>,
+    broadcaster: &Broadcaster<N>,
+    deadline: &Deadline,
+    pending_dir: Option<&Path>,
+) {
+    loop {
+        let Some(ProofJob { info, authorization, notify }) = queue.pop() else {
+            return;
+        };
+        let proved = prove_job(prover, executor, broadcaster, deadline, pending_dir, authorization, notify);
+        queue.finish(info.id);
+        if proved {
+            throttle(deadline);
+        }
+    }
+}
+
+/// Proves, verifies and broadcasts one job, returning whether a proof was generated.
+fn prove_job<N: Network, C: ConsensusStorage<N>>(
+    prover: &dyn Prover<N>,
+    executor: &Executor<N, C>,
+    broadcaster: &Broadcaster<N>,
+    deadline: &Deadline,
+    pending_dir: Option<&Path>,
+    authorization: Authorization<N>,
+    notify: oneshot::Sender<ProofResult<N>>,
+) -> bool {
+    if deadline.get().is_some_and(|deadline| Instant::now() >= *deadline) {
+        if let Some(pending_dir) = pending_dir {
+            if let Err(err) = persist(pending_dir, &authorization) {
+                error!("persist pending proof: {}", err);
             }
-            sleep(Duration::from_secs(30));
         }
-    });
-    tx
-}
\ No newline at end of file
+        return false;
+    }
+    let timer = METRICS.proof_latency.start_timer();
+    let result = prover.prove(authorization);
+    timer.observe_duration();
+    match result {
+        Ok(Proven::Transaction(transaction)) => {
+            // An invalid transaction is never broadcast, the game hears why instead.
+            if let Err(err) = executor.verify(&transaction) {
+                error!("Transaction {} failed verification: {}", transaction.id(), err);
+                METRICS.broadcasts.with_label_values(&["invalid"]).inc();
+                let _ = notify.send(Err(err.context(format!("Transaction {} is invalid", transaction.id()))));
+                return true;
+            }
+            let record = broadcaster.broadcast(*transaction);
+            match record.status {
+                BroadcastStatus::Broadcast => info!("Broadcast transaction {}", record.transaction_id),
+                // Retried by the rebroadcast task, players still get the transaction id.
+                _ => warn!("Broadcast of transaction {} failed", record.transaction_id),
+            }
+            if notify.send(Ok(record.transaction_id)).is_err() {
+                warn!("Notify channel broken for transaction {}", record.transaction_id);
+            }
+            true
+        }
+        Ok(Proven::Mock(transaction_id)) => {
+            info!("Dry run, not proving transaction {}", transaction_id);
+            let _ = notify.send(Ok(transaction_id));
+            false
+        }
+        Err(err) => {
+            error!("Execute error: {}", err);
+            let _ = notify.send(Err(err));
+            true
+        }
+    }
+}
+
+// Wait between proofs, but stop waiting as soon as shutdown begins.
+fn throttle(deadline: &Deadline) {
+    let until = Instant::now() + THROTTLE;
+    while deadline.get().is_none() && Instant::now() < until {
+        sleep(Duration::from_millis(500));
+    }
+}
+
+fn persist<N: Network>(dir: &Path, authorization: &Authorization<N>) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+    let path = dir.join(format!("{nanos}.json"));
+    fs::write(&path, serde_json::to_string(&authorization.to_vec_deque())?)?;
+    info!("Persisted pending proof {}", path.display());
+    Ok(())
+}
+
+/// Loads and removes the authorizations persisted by a previous shutdown.
+pub fn load_pending<N: Network>(dir: &Path) -> Result<Vec<Authorization<N>>> {
+    let mut authorizations = vec![];
+    if !dir.exists() {
+        return Ok(authorizations);
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        let requests: VecDeque<Request<N>> = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        authorizations.push(Authorization::new(&requests.into_iter().collect::<Vec<_>>()));
+        fs::remove_file(&path)?;
+    }
+    Ok(authorizations)
+}
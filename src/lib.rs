@@ -1,8 +1,21 @@
+mod api;
+pub mod broadcast;
+pub mod board;
+pub mod bot;
+pub mod client;
 mod executor;
-mod player;
+mod games;
+pub mod limits;
+mod metrics;
+pub mod node;
+pub mod player;
+pub mod protocol;
+pub mod prover;
+pub mod queue;
+pub mod records;
 mod requests;
 // mod response;
 pub mod server;
-mod table;
+pub mod table;
 pub mod utils;
-mod generator;
\ No newline at end of file
+mod generator;
@@ -0,0 +1,339 @@
+//! Abuse protection for the game socket.
+//!
+//! Every connection, message and move is counted against the client's IP and,
+//! once the handshake proved it, its Aleo address, each with its own token
+//! bucket. An address anyone can claim is never counted, or one client could
+//! spend another's limits. Addresses can be allowed or denied outright. A
+//! proven address may only queue for or play so many games at once, and so
+//! may all unproven clients of one IP together.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    hash::Hash,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, ensure, Result};
+use snarkvm::prelude::{Address, FromStr, Network};
+
+use crate::metrics::METRICS;
+
+#[derive(Clone, Debug)]
+pub struct LimitsConfig {
+    /// Game connections per IP and per address and minute, reconnects included. 0 disables the limit.
+    pub connections_per_minute: u32,
+    /// Messages per IP and per address and minute. 0 disables the limit.
+    pub messages_per_minute: u32,
+    /// Moves, each one a proof, per IP and per address and hour. 0 disables the limit.
+    pub moves_per_hour: u32,
+    /// Games a proven address, or all unproven clients of an IP, may queue for or play at the same time.
+    pub max_games_per_address: usize,
+    /// Largest WebSocket message accepted, in bytes.
+    pub max_message_size: usize,
+    /// If not empty, only these addresses may play, and they have to prove it.
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            connections_per_minute: 10,
+            messages_per_minute: 120,
+            moves_per_hour: 300,
+            max_games_per_address: 2,
+            max_message_size: 64 * 1024,
+            allow: vec![],
+            deny: vec![],
+        }
+    }
+}
+
+/// Buckets not touched for this long are full again and forgotten.
+const IDLE_BUCKET: Duration = Duration::from_secs(3600);
+
+/// Token buckets holding up to `limit` tokens per key, refilled at `limit` per `period`.
+struct RateLimiter<K> {
+    limit: u32,
+    period: Duration,
+    buckets: Mutex<HashMap<K, (f64, Instant)>>,
+}
+
+impl<K: Hash + Eq + Clone> RateLimiter<K> {
+    fn new(limit: u32, period: Duration) -> Self {
+        Self { limit, period, buckets: Default::default() }
+    }
+
+    /// Takes a token for every key, or none at all if one of them has none left.
+    fn take(&self, keys: &[K]) -> bool {
+        if self.limit == 0 {
+            return true;
+        }
+        let limit = self.limit as f64;
+        let rate = limit / self.period.as_secs_f64();
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > 10_000 {
+            buckets.retain(|_, (_, last)| now.duration_since(*last) < IDLE_BUCKET);
+        }
+        let mut available = true;
+        for key in keys {
+            let (tokens, last) = buckets.entry(key.clone()).or_insert((limit, now));
+            *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * rate).min(limit);
+            *last = now;
+            available &= *tokens >= 1.0;
+        }
+        if available {
+            for key in keys {
+                buckets.get_mut(key).expect("refilled above").0 -= 1.0;
+            }
+        }
+        available
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Source<N: Network> {
+    Ip(IpAddr),
+    Address(Address<N>),
+}
+
+/// Shared by the socket handler and every game.
+#[derive(Clone)]
+pub struct Limits<N: Network> {
+    inner: Arc<Inner<N>>,
+}
+
+struct Inner<N: Network> {
+    config: LimitsConfig,
+    allow: HashSet<Address<N>>,
+    deny: HashSet<Address<N>>,
+    connections: RateLimiter<Source<N>>,
+    messages: RateLimiter<Source<N>>,
+    moves: RateLimiter<Source<N>>,
+    /// Game slots held per proven address or unproven IP, see [`GameSlot`].
+    games: Mutex<HashMap<Source<N>, usize>>,
+}
+
+/// Held from a hello until its game is over, releases itself on drop.
+pub struct GameSlot<N: Network> {
+    inner: Arc<Inner<N>>,
+    source: Source<N>,
+}
+
+impl<N: Network> fmt::Debug for GameSlot<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("GameSlot").field(&self.source).finish()
+    }
+}
+
+impl<N: Network> Drop for GameSlot<N> {
+    fn drop(&mut self) {
+        let mut games = self.inner.games.lock().unwrap();
+        if let Some(count) = games.get_mut(&self.source) {
+            *count -= 1;
+            if *count == 0 {
+                games.remove(&self.source);
+            }
+        }
+    }
+}
+
+impl<N: Network> Limits<N> {
+    pub fn new(config: LimitsConfig) -> Result<Self> {
+        ensure!(config.max_games_per_address > 0, "An address must be allowed at least one game");
+        ensure!(config.max_message_size > 0, "The message size limit must be positive");
+        let parse = |addresses: &[String]| {
+            addresses.iter().map(|address| Address::from_str(address)).collect::<Result<HashSet<_>>>()
+        };
+        let inner = Inner {
+            allow: parse(&config.allow)?,
+            deny: parse(&config.deny)?,
+            connections: RateLimiter::new(config.connections_per_minute, Duration::from_secs(60)),
+            messages: RateLimiter::new(config.messages_per_minute, Duration::from_secs(60)),
+            moves: RateLimiter::new(config.moves_per_hour, Duration::from_secs(3600)),
+            games: Default::default(),
+            config,
+        };
+        Ok(Self { inner: Arc::new(inner) })
+    }
+
+    pub fn max_message_size(&self) -> usize {
+        self.inner.config.max_message_size
+    }
+
+    /// Checked before the WebSocket upgrade, when only the IP is known.
+    pub fn connect(&self, ip: IpAddr) -> Result<()> {
+        if !self.inner.connections.take(&[Source::Ip(ip)]) {
+            METRICS.rate_limited.with_label_values(&["connection"]).inc();
+            bail!("Too many connections from {ip}");
+        }
+        Ok(())
+    }
+
+    /// Checked after the handshake named `address`, which `proven` tells whether the client holds the key of.
+    pub fn admit(&self, address: &Address<N>, proven: bool) -> Result<()> {
+        let inner = &self.inner;
+        let allowed = inner.allow.is_empty() || (proven && inner.allow.contains(address));
+        if inner.deny.contains(address) || !allowed {
+            METRICS.rate_limited.with_label_values(&["denied"]).inc();
+            bail!("Address {address} is not allowed to play");
+        }
+        if proven && !inner.connections.take(&[Source::Address(*address)]) {
+            METRICS.rate_limited.with_label_values(&["connection"]).inc();
+            bail!("Too many connections for {address}");
+        }
+        Ok(())
+    }
+
+    /// A slot for one more game of the proven `address`, or of `ip` if the address is not proven.
+    pub fn claim_game(&self, ip: IpAddr, address: Option<&Address<N>>) -> Result<GameSlot<N>> {
+        let source = address.map_or(Source::Ip(ip), |address| Source::Address(*address));
+        let mut games = self.inner.games.lock().unwrap();
+        let count = games.entry(source).or_default();
+        if *count >= self.inner.config.max_games_per_address {
+            METRICS.rate_limited.with_label_values(&["games"]).inc();
+            match address {
+                Some(address) => bail!("{address} is already playing {count} games"),
+                None => bail!("{ip} is already playing {count} games, prove your address to play more"),
+            }
+        }
+        *count += 1;
+        Ok(GameSlot { inner: self.inner.clone(), source })
+    }
+
+    /// Counts a message from `ip` and the proven `address`, false if either is over its limit.
+    pub fn message(&self, ip: IpAddr, address: Option<&Address<N>>) -> bool {
+        self.take(&self.inner.messages, ip, address, "message")
+    }
+
+    /// Counts a move, and so a proof, false if either is over its limit.
+    pub fn proof(&self, ip: IpAddr, address: Option<&Address<N>>) -> bool {
+        self.take(&self.inner.moves, ip, address, "move")
+    }
+
+    fn take(&self, limiter: &RateLimiter<Source<N>>, ip: IpAddr, address: Option<&Address<N>>, kind: &str) -> bool {
+        let mut sources = vec![Source::Ip(ip)];
+        sources.extend(address.map(|address| Source::Address(*address)));
+        let allowed = limiter.take(&sources);
+        if !allowed {
+            METRICS.rate_limited.with_label_values(&[kind]).inc();
+        }
+        allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use snarkvm::prelude::{PrivateKey, Testnet3};
+
+    use super::*;
+
+    fn address() -> Address<Testnet3> {
+        Address::try_from(PrivateKey::<Testnet3>::new(&mut rand::thread_rng()).unwrap()).unwrap()
+    }
+
+    fn limits(config: LimitsConfig) -> Limits<Testnet3> {
+        Limits::new(config).unwrap()
+    }
+
+    const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+    #[test]
+    fn buckets_hold_up_to_the_limit() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(3600));
+        assert!(limiter.take(&[1]));
+        assert!(limiter.take(&[1]));
+        assert!(!limiter.take(&[1]));
+        assert!(limiter.take(&[2]), "keys have buckets of their own");
+    }
+
+    #[test]
+    fn buckets_refill_over_the_period() {
+        let limiter = RateLimiter::new(1, Duration::from_millis(50));
+        assert!(limiter.take(&[1]));
+        assert!(!limiter.take(&[1]));
+        sleep(Duration::from_millis(60));
+        assert!(limiter.take(&[1]));
+    }
+
+    #[test]
+    fn a_zero_limit_never_refuses() {
+        let limiter = RateLimiter::new(0, Duration::from_secs(60));
+        assert!((0..1000).all(|_| limiter.take(&[1])));
+    }
+
+    #[test]
+    fn an_empty_bucket_spends_no_token_of_the_others() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(3600));
+        assert!(limiter.take(&[1]));
+        assert!(limiter.take(&[1]));
+        assert!(!limiter.take(&[2, 1]));
+        assert!(limiter.take(&[2]));
+        assert!(limiter.take(&[2]));
+        assert!(!limiter.take(&[2]));
+    }
+
+    #[test]
+    fn denied_addresses_are_turned_away() {
+        let (denied, other) = (address(), address());
+        let limits = limits(LimitsConfig { deny: vec![denied.to_string()], ..Default::default() });
+        assert!(limits.admit(&denied, true).is_err());
+        assert!(limits.admit(&denied, false).is_err());
+        assert!(limits.admit(&other, false).is_ok());
+    }
+
+    #[test]
+    fn allowed_addresses_have_to_prove_it() {
+        let (allowed, other) = (address(), address());
+        let limits = limits(LimitsConfig { allow: vec![allowed.to_string()], ..Default::default() });
+        assert!(limits.admit(&allowed, true).is_ok());
+        assert!(limits.admit(&allowed, false).is_err());
+        assert!(limits.admit(&other, true).is_err());
+    }
+
+    #[test]
+    fn only_proven_addresses_count_connections() {
+        let address = address();
+        let limits = limits(LimitsConfig { connections_per_minute: 1, ..Default::default() });
+        assert!((0..5).all(|_| limits.admit(&address, false).is_ok()));
+        assert!(limits.admit(&address, true).is_ok());
+        assert!(limits.admit(&address, true).is_err());
+    }
+
+    #[test]
+    fn games_are_capped_per_proven_address() {
+        let (address, other) = (address(), address());
+        let limits = limits(LimitsConfig { max_games_per_address: 2, ..Default::default() });
+        let _first = limits.claim_game(IP, Some(&address)).unwrap();
+        let _second = limits.claim_game(IP, Some(&address)).unwrap();
+        assert!(limits.claim_game(IP, Some(&address)).is_err());
+        assert!(limits.claim_game(IP, Some(&other)).is_ok());
+        assert!(limits.claim_game(IP, None).is_ok(), "unproven clients are counted by IP");
+    }
+
+    #[test]
+    fn games_of_unproven_clients_are_capped_per_ip() {
+        let limits = limits(LimitsConfig { max_games_per_address: 1, ..Default::default() });
+        let _slot = limits.claim_game(IP, None).unwrap();
+        assert!(limits.claim_game(IP, None).is_err());
+        assert!(limits.claim_game("10.0.0.1".parse().unwrap(), None).is_ok());
+    }
+
+    #[test]
+    fn dropped_slots_are_released() {
+        let address = address();
+        let limits = limits(LimitsConfig { max_games_per_address: 1, ..Default::default() });
+        let slot = limits.claim_game(IP, Some(&address)).unwrap();
+        assert!(limits.claim_game(IP, Some(&address)).is_err());
+        drop(slot);
+        let slot = limits.claim_game(IP, Some(&address)).unwrap();
+        drop(slot);
+        assert!(limits.inner.games.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,111 @@
+use std::sync::LazyLock;
+
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    pub connected_players: IntGauge,
+    pub active_tables: IntGauge,
+    pub moves: IntCounterVec,
+    pub evaluate_latency: Histogram,
+    pub proof_latency: Histogram,
+    pub prover_queue_depth: IntGauge,
+    pub broadcasts: IntCounterVec,
+    pub websocket_errors: IntCounter,
+    pub rate_limited: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("superbuffer".to_string()), None)
+            .expect("valid registry prefix");
+        let connected_players =
+            IntGauge::new("connected_players", "Players with an open game socket").unwrap();
+        let active_tables = IntGauge::new("active_tables", "Games currently being played").unwrap();
+        let moves = IntCounterVec::new(
+            Opts::new("moves_total", "Requests accepted per battleship function"),
+            &["function"],
+        )
+        .unwrap();
+        let evaluate_latency = Histogram::with_opts(HistogramOpts::new(
+            "evaluate_seconds",
+            "Latency of Executor::evaluate",
+        ))
+        .unwrap();
+        // Proofs take seconds to minutes, the default buckets stop at 10s.
+        let proof_latency = Histogram::with_opts(
+            HistogramOpts::new("proof_seconds", "Latency of proof generation")
+                .buckets(vec![1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0]),
+        )
+        .unwrap();
+        let prover_queue_depth =
+            IntGauge::new("prover_queue_depth", "Proof requests waiting for the generator").unwrap();
+        let broadcasts = IntCounterVec::new(
+            Opts::new("broadcasts_total", "Transaction broadcasts by result, invalid ones failed local verification"),
+            &["result"],
+        )
+        .unwrap();
+        let websocket_errors =
+            IntCounter::new("websocket_errors_total", "Failed WebSocket reads and writes").unwrap();
+        let rate_limited = IntCounterVec::new(
+            Opts::new("rate_limited_total", "Connections, messages and moves turned away by the limits"),
+            &["kind"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(connected_players.clone())).unwrap();
+        registry.register(Box::new(active_tables.clone())).unwrap();
+        registry.register(Box::new(moves.clone())).unwrap();
+        registry.register(Box::new(evaluate_latency.clone())).unwrap();
+        registry.register(Box::new(proof_latency.clone())).unwrap();
+        registry.register(Box::new(prover_queue_depth.clone())).unwrap();
+        registry.register(Box::new(broadcasts.clone())).unwrap();
+        registry.register(Box::new(websocket_errors.clone())).unwrap();
+        registry.register(Box::new(rate_limited.clone())).unwrap();
+
+        Self {
+            registry,
+            connected_players,
+            active_tables,
+            moves,
+            evaluate_latency,
+            proof_latency,
+            prover_queue_depth,
+            broadcasts,
+            websocket_errors,
+            rate_limited,
+        }
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut buffer = vec![];
+        let encoder = TextEncoder::new();
+        if let Err(err) = encoder.encode(&self.registry.gather(), &mut buffer) {
+            tracing::error!("encode metrics: {}", err);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+/// Increments a gauge on creation and decrements it on drop.
+#[derive(Debug)]
+pub struct GaugeGuard(&'static IntGauge);
+
+impl GaugeGuard {
+    pub fn new(gauge: &'static IntGauge) -> Self {
+        gauge.inc();
+        Self(gauge)
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
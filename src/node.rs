@@ -0,0 +1,301 @@
+//! HTTP client for the snarkOS REST API.
+//!
+//! Every request goes through [`NodeClient`], which applies timeouts, retries
+//! transient failures with exponential backoff and fails over between the
+//! configured endpoints. Errors are classified so callers can tell a missing
+//! program or transaction from a node that is merely unreachable.
+//!
+//! snarkVM fetches state roots and state paths while proving through its own
+//! bare HTTP client; [`QueryProxy`] points it at a loopback endpoint that
+//! answers through a [`NodeClient`] instead.
+
+use std::{
+    fmt,
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use axum::{
+    extract::State,
+    http::{StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use snarkvm::prelude::{Deployment, Network, Program, ProgramID, Transaction};
+use tokio::sync::oneshot;
+use tracing::{error, warn};
+
+use crate::utils::network_path;
+
+#[derive(Clone, Debug)]
+pub struct NodeConfig {
+    /// Base URLs of the nodes, e.g. `http://127.0.0.1:3030`, tried in order.
+    pub endpoints: Vec<String>,
+    /// Timeout of a single request, connecting included.
+    pub timeout: Duration,
+    /// How often a request is retried across all endpoints after a transient failure.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for every further retry.
+    pub backoff: Duration,
+    /// Sent as `Authorization: Bearer <token>`.
+    pub token: Option<String>,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            endpoints: vec!["http://127.0.0.1:3030".to_string()],
+            timeout: Duration::from_secs(10),
+            retries: 3,
+            backoff: Duration::from_millis(500),
+            token: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum NodeError {
+    /// The node answered 404, the program, transaction or block does not exist (yet).
+    NotFound(String),
+    /// The node refused the request, e.g. an invalid transaction. Retrying will not help.
+    Rejected { status: u16, message: String },
+    /// Timeouts, connection failures and server errors on every endpoint.
+    Transient(String),
+    /// The request could not be built, e.g. for a network the node API has no path for.
+    Invalid(String),
+}
+
+impl NodeError {
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, NodeError::NotFound(_))
+    }
+}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeError::NotFound(url) => write!(f, "Not found: {url}"),
+            NodeError::Rejected { status, message } => write!(f, "Rejected with {status}: {message}"),
+            NodeError::Transient(message) => write!(f, "Node unavailable: {message}"),
+            NodeError::Invalid(message) => write!(f, "Invalid request: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for NodeError {}
+
+/// Shared, cheaply cloneable client for one or more nodes.
+#[derive(Clone)]
+pub struct NodeClient {
+    agent: ureq::Agent,
+    config: Arc<NodeConfig>,
+    /// The endpoint that answered last, tried first next time.
+    preferred: Arc<AtomicUsize>,
+}
+
+impl NodeClient {
+    pub fn new(config: NodeConfig) -> anyhow::Result<Self> {
+        anyhow::ensure!(!config.endpoints.is_empty(), "No node endpoints configured");
+        let agent = ureq::AgentBuilder::new()
+            .timeout(config.timeout)
+            .user_agent(concat!("superbuffer/", env!("CARGO_PKG_VERSION")))
+            .build();
+        Ok(Self { agent, config: Arc::new(config), preferred: Default::default() })
+    }
+
+    /// A client for a single node with the default timeouts and retries.
+    pub fn from_url(url: &str) -> anyhow::Result<Self> {
+        Self::new(NodeConfig { endpoints: vec![url.to_string()], ..Default::default() })
+    }
+
+    /// The endpoint currently preferred. snarkVM's `Query` takes a [`QueryProxy`] URL instead.
+    pub fn endpoint(&self) -> &str {
+        &self.config.endpoints[self.preferred.load(Ordering::Relaxed) % self.config.endpoints.len()]
+    }
+
+    pub fn endpoints(&self) -> &[String] {
+        &self.config.endpoints
+    }
+
+    /// GETs `path` below the network segment, e.g. `latest/height`.
+    pub fn get<N: Network, T: DeserializeOwned>(&self, path: &str) -> Result<T, NodeError> {
+        self.send::<N, _, ()>(path, None)
+    }
+
+    /// POSTs `body` as JSON to `path` below the network segment.
+    pub fn post<N: Network, T: DeserializeOwned, B: Serialize>(&self, path: &str, body: &B) -> Result<T, NodeError> {
+        self.send::<N, _, _>(path, Some(body))
+    }
+
+    /// POSTs to one endpoint only, without failing over. Used to broadcast to every node in turn.
+    pub fn post_to<N: Network, T: DeserializeOwned, B: Serialize>(
+        &self,
+        endpoint: &str,
+        path: &str,
+        body: &B,
+    ) -> Result<T, NodeError> {
+        let url = self.url::<N>(endpoint, path)?;
+        self.retry(|| self.call(&url, Some(body)))
+    }
+
+    pub fn program<N: Network>(&self, program_id: &ProgramID<N>) -> Result<Program<N>, NodeError> {
+        self.get::<N, _>(&format!("program/{program_id}"))
+    }
+
+    pub fn transaction<N: Network>(&self, id: &N::TransactionID) -> Result<Transaction<N>, NodeError> {
+        self.get::<N, _>(&format!("transaction/{id}"))
+    }
+
+    /// The deployment of `program_id`, looked up through its deployment transaction.
+    pub fn deployment<N: Network>(&self, program_id: &ProgramID<N>) -> Result<Deployment<N>, NodeError> {
+        let id: N::TransactionID = self.get::<N, _>(&format!("find/transactionID/deployment/{program_id}"))?;
+        self.transaction::<N>(&id)?
+            .deployment()
+            .cloned()
+            .ok_or_else(|| NodeError::NotFound(format!("deployment of {program_id}")))
+    }
+
+    fn url<N: Network>(&self, endpoint: &str, path: &str) -> Result<String, NodeError> {
+        let network = network_path::<N>().map_err(|err| NodeError::Invalid(err.to_string()))?;
+        Ok(format!("{}/{network}/{path}", endpoint.trim_end_matches('/')))
+    }
+
+    fn send<N: Network, T: DeserializeOwned, B: Serialize>(&self, path: &str, body: Option<&B>) -> Result<T, NodeError> {
+        let endpoints = &self.config.endpoints;
+        self.retry(|| {
+            let start = self.preferred.load(Ordering::Relaxed);
+            let mut not_found = None;
+            let mut transient = None;
+            for index in (start..start + endpoints.len()).map(|index| index % endpoints.len()) {
+                let url = self.url::<N>(&endpoints[index], path)?;
+                match self.call(&url, body) {
+                    Ok(value) => {
+                        self.preferred.store(index, Ordering::Relaxed);
+                        return Ok(value);
+                    }
+                    // A lagging node may not know the object yet, ask the others too.
+                    Err(err @ NodeError::NotFound(_)) => not_found = Some(err),
+                    Err(err @ NodeError::Transient(_)) => {
+                        warn!("{}", err);
+                        transient = Some(err);
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            // A live node that does not know the object outweighs one that could not be asked.
+            Err(not_found.or(transient).expect("at least one endpoint"))
+        })
+    }
+
+    /// Runs `request` until it succeeds or fails with anything but a transient error.
+    fn retry<T>(&self, mut request: impl FnMut() -> Result<T, NodeError>) -> Result<T, NodeError> {
+        let mut delay = self.config.backoff;
+        for _ in 0..self.config.retries {
+            match request() {
+                Err(NodeError::Transient(_)) => {
+                    thread::sleep(delay);
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+        request()
+    }
+
+    fn call<T: DeserializeOwned, B: Serialize>(&self, url: &str, body: Option<&B>) -> Result<T, NodeError> {
+        let request = match body {
+            Some(_) => self.agent.post(url),
+            None => self.agent.get(url),
+        };
+        let request = match &self.config.token {
+            Some(token) => request.set("Authorization", &format!("Bearer {token}")),
+            None => request,
+        };
+        let response = match body {
+            Some(body) => request.send_json(body),
+            None => request.call(),
+        };
+        match response {
+            Ok(response) => response.into_json().map_err(|err| NodeError::Transient(format!("{url}: {err}"))),
+            Err(ureq::Error::Status(404, _)) => Err(NodeError::NotFound(url.to_string())),
+            Err(ureq::Error::Status(status, response)) if status == 429 || status >= 500 => {
+                Err(NodeError::Transient(format!("{url}: {status} {}", response.into_string().unwrap_or_default())))
+            }
+            Err(ureq::Error::Status(status, response)) => {
+                Err(NodeError::Rejected { status, message: response.into_string().unwrap_or_default() })
+            }
+            Err(ureq::Error::Transport(err)) => Err(NodeError::Transient(format!("{url}: {err}"))),
+        }
+    }
+}
+
+/// A loopback endpoint for snarkVM's `Query`. The routes snarkVM asks for while proving are
+/// answered through a [`NodeClient`], with its token, timeouts, retries and failover.
+pub struct QueryProxy {
+    url: String,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl QueryProxy {
+    pub fn start<N: Network>(node: NodeClient) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        let app = Router::new()
+            .route("/:network/latest/stateRoot", get(forward::<N>))
+            .route("/:network/statePath/:commitment", get(forward::<N>))
+            .route("/:network/program/:program_id", get(forward::<N>))
+            .with_state(node);
+        // A runtime of its own, snarkVM queries from blocking code that may run outside of any runtime.
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        thread::Builder::new().name("query-proxy".to_string()).spawn(move || {
+            let served = runtime.block_on(async move {
+                axum::Server::from_tcp(listener)?
+                    .serve(app.into_make_service())
+                    .with_graceful_shutdown(async {
+                        let _ = stopped.await;
+                    })
+                    .await
+            });
+            if let Err(err) = served {
+                error!("query proxy: {}", err);
+            }
+        })?;
+        Ok(Self { url, shutdown: Some(shutdown) })
+    }
+
+    /// The base URL to build snarkVM's `Query` from.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+impl Drop for QueryProxy {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+async fn forward<N: Network>(State(node): State<NodeClient>, uri: Uri) -> Response {
+    // snarkVM names the network itself, the client adds the one it is configured for.
+    let path = uri.path().trim_start_matches('/').split_once('/').map(|(_, path)| path.to_string()).unwrap_or_default();
+    match tokio::task::spawn_blocking(move || node.get::<N, serde_json::Value>(&path)).await {
+        Ok(Ok(value)) => Json(value).into_response(),
+        Ok(Err(err)) => {
+            warn!("query proxy: {}", err);
+            let status = if err.is_not_found() { StatusCode::NOT_FOUND } else { StatusCode::BAD_GATEWAY };
+            (status, err.to_string()).into_response()
+        }
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
@@ -1,39 +1,92 @@
-use std::{collections::VecDeque, sync::Arc};
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use anyhow::Context;
+use anyhow::{bail, Context, Result};
 use axum::extract::ws::{Message, WebSocket};
 use futures::{
     stream::{SplitSink, SplitStream},
     SinkExt, StreamExt,
 };
-use snarkvm::prelude::*;
-use tokio::sync::Mutex;
+use snarkvm::prelude::{Address, Network};
+use tokio::{
+    sync::Mutex,
+    time::{sleep_until, Instant},
+};
+use tracing::debug;
 
 use crate::{
+    limits::GameSlot,
+    metrics::{GaugeGuard, METRICS},
+    protocol::{decode_client_message, encode_player_message, ClientMessage, PlayerMessage, SealedRecord, LEGACY_VERSION},
     table::Status,
 };
 
+/// Server side heartbeat settings for player connections.
+#[derive(Clone, Copy, Debug)]
+pub struct KeepAlive {
+    /// How often the server pings a player while waiting for a message.
+    pub ping_interval: Duration,
+    /// A player that sends nothing, not even a pong, for this long is disconnected.
+    pub idle_timeout: Duration,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(15),
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Player<N: Network> {
     address: Address<N>,
+    /// Whether the handshake proved the address, only then is it counted by the limits.
+    proven: bool,
+    remote: Arc<std::sync::Mutex<IpAddr>>,
+    game_slot: Arc<std::sync::Mutex<Option<GameSlot<N>>>>,
+    version: Arc<AtomicU32>,
+    keepalive: KeepAlive,
+    last_seen: Arc<std::sync::Mutex<Instant>>,
+    // Kept across `recv_text` calls, the game loop drops a player's call whenever the opponent speaks.
+    next_ping: Arc<std::sync::Mutex<Instant>>,
     sink: Arc<Mutex<SplitSink<WebSocket, Message>>>,
     stream: Arc<Mutex<SplitStream<WebSocket>>>,
-}
-
-#[derive(Serialize)]
-pub enum PlayerMessage<N: Network> {
-    Start(String, Address<N>),
-    GameStatus(Status, Vec<Record<N, Plaintext<N>>>),
-    TxID(N::TransactionID),
+    // Counts the player as connected until the last clone is dropped.
+    _connected: Arc<GaugeGuard>,
 }
 
 impl<N: Network> Player<N> {
-    pub fn new(address: Address<N>, socket: WebSocket) -> Self {
+    /// `proven` tells whether the handshake proved `address`, see [`Limits::claim_game`](crate::limits::Limits::claim_game).
+    pub fn new(
+        address: Address<N>,
+        proven: bool,
+        version: u32,
+        keepalive: KeepAlive,
+        socket: WebSocket,
+        remote: IpAddr,
+        game_slot: Option<GameSlot<N>>,
+    ) -> Self {
         let (sink, stream) = socket.split();
         Self {
             address,
+            proven,
+            remote: Arc::new(std::sync::Mutex::new(remote)),
+            game_slot: Arc::new(std::sync::Mutex::new(game_slot)),
+            version: Arc::new(AtomicU32::new(version)),
+            keepalive,
+            last_seen: Arc::new(std::sync::Mutex::new(Instant::now())),
+            next_ping: Arc::new(std::sync::Mutex::new(Instant::now() + keepalive.ping_interval)),
             sink: Arc::new(Mutex::new(sink)),
             stream: Arc::new(Mutex::new(stream)),
+            _connected: Arc::new(GaugeGuard::new(&METRICS.connected_players)),
         }
     }
 
@@ -41,40 +94,104 @@ impl<N: Network> Player<N> {
         &self.address
     }
 
+    /// The IP and, if proven, the address the limits count this player's messages and moves against.
+    pub fn limits_key(&self) -> (IpAddr, Option<&Address<N>>) {
+        (*self.remote.lock().unwrap(), self.proven.then_some(&self.address))
+    }
+
+    /// Hands over the game slot, for the game to release once it is over.
+    pub fn take_game_slot(&self) -> Option<GameSlot<N>> {
+        self.game_slot.lock().unwrap().take()
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version.load(Ordering::Relaxed)
+    }
+
+    /// Swaps in the socket of a reconnected client, shared by every clone of this player.
+    pub async fn reconnect(&self, version: u32, socket: WebSocket, remote: IpAddr) {
+        let (sink, stream) = socket.split();
+        *self.remote.lock().unwrap() = remote;
+        *self.sink.lock().await = sink;
+        *self.stream.lock().await = stream;
+        self.version.store(version, Ordering::Relaxed);
+        self.touch();
+        *self.next_ping.lock().unwrap() = Instant::now() + self.keepalive.ping_interval;
+    }
+
+    fn touch(&self) {
+        *self.last_seen.lock().unwrap() = Instant::now();
+    }
+
+    fn idle_deadline(&self) -> Instant {
+        *self.last_seen.lock().unwrap() + self.keepalive.idle_timeout
+    }
+
     pub async fn notify(&self, message: PlayerMessage<N>) -> Result<()> {
-        let message = Message::Text(serde_json::to_string(&message)?);
+        let message = Message::Text(encode_player_message(self.version(), &message)?);
+        self.send(message).await
+    }
+
+    async fn send(&self, message: Message) -> Result<()> {
         let mut sink = self.sink.lock().await;
-        sink.send(message).await.context("notify")
+        sink.send(message)
+            .await
+            .inspect_err(|_| METRICS.websocket_errors.inc())
+            .context("notify")
     }
 
     pub async fn recv(&self) -> Result<Option<Message>> {
-        self.stream.lock().await.next().await.transpose().context("recv")
+        self.stream
+            .lock()
+            .await
+            .next()
+            .await
+            .transpose()
+            .inspect_err(|_| METRICS.websocket_errors.inc())
+            .context("recv")
     }
 
-    pub async fn recv_request(&self) -> Result<VecDeque<Request<N>>> {
-        let msg = self.recv().await?;
-        let request = match msg {
-            Some(Message::Text(msg)) => serde_json::from_str(&msg)?,
-            None => bail!("Disconnect"),
-            _ => bail!("Not follow protocol"),
-        };
-        Ok(request)
+    /// Waits for the next text frame, failing once the connection is gone.
+    ///
+    /// Control frames are handled here: pings are answered, and the player is
+    /// pinged every `ping_interval` and dropped after `idle_timeout` of silence.
+    pub async fn recv_text(&self) -> Result<String> {
+        loop {
+            let next_ping = *self.next_ping.lock().unwrap();
+            let msg = tokio::select! {
+                msg = self.recv() => msg?,
+                _ = sleep_until(next_ping) => {
+                    *self.next_ping.lock().unwrap() = Instant::now() + self.keepalive.ping_interval;
+                    self.send(Message::Ping(vec![])).await?;
+                    continue;
+                }
+                _ = sleep_until(self.idle_deadline()) => bail!("Idle timeout"),
+            };
+            self.touch();
+            match msg {
+                Some(Message::Text(msg)) => return Ok(msg),
+                Some(Message::Ping(payload)) => self.send(Message::Pong(payload)).await?,
+                Some(Message::Pong(_)) => {}
+                Some(Message::Binary(_)) => debug!("Ignore binary frame from {}", self.address),
+                Some(Message::Close(_)) | None => bail!("Disconnect"),
+            }
+        }
     }
 
-    pub async fn notify_start(&self, id: &str, address: &Address<N>) -> Result<()> {
-        self.notify(PlayerMessage::Start(id.to_string(), *address)).await
+    pub fn decode(&self, text: &str) -> Result<ClientMessage<N>> {
+        decode_client_message(self.version(), text)
     }
 
-    pub async fn notify_status(&self, status: Status, response: Response<N>) -> Result<()> {
-        let mut records = vec![];
-        response.outputs().iter().for_each(|val| {
-            if let Value::Record(record) = val {
-                let owner: Address<N> = *record.owner().clone();
-                if self.address == owner {
-                    records.push(record.clone());
-                }
-            };
-        });
+    pub async fn notify_start(&self, id: &str, address: &Address<N>, game_id: u64) -> Result<()> {
+        self.notify(PlayerMessage::Start(id.to_string(), *address)).await?;
+        if self.version() != LEGACY_VERSION {
+            self.notify(PlayerMessage::GameId(game_id)).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends the new status along with the player's records, sealed by the server's record mode.
+    pub async fn notify_status(&self, status: Status, records: Vec<SealedRecord<N>>) -> Result<()> {
         self.notify(PlayerMessage::GameStatus(status, records)).await
     }
 
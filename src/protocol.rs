@@ -0,0 +1,188 @@
+//! Wire protocol spoken over the `/battleship` WebSocket.
+//!
+//! Every frame is a JSON text frame. A connection opens with a
+//! [`ClientMessage::Hello`] (or [`ClientMessage::Reconnect`]) carrying the
+//! client's protocol version; the server answers with
+//! [`PlayerMessage::Welcome`] and from then on wraps every message in an
+//! envelope `{"version": PROTOCOL_VERSION, "message": <message>}`.
+//!
+//! Version 0 is the legacy protocol: the first frame is a bare address,
+//! later frames are a bare `VecDeque<Request>` and server messages are not
+//! wrapped. It is still accepted so deployed clients keep working.
+//!
+//! Version 2 sends records as [`SealedRecord`]s: encrypted to their owner or
+//! reduced to their commitment, depending on the server's [`RecordMode`].
+//! Older clients only understand plaintext records and are turned away
+//! unless the server runs in [`RecordMode::Plaintext`].
+//!
+//! Version 3 proves the address: the server answers the hello with a
+//! [`PlayerMessage::Challenge`], which the client signs with the address's
+//! private key ([`sign_challenge`]) and returns in [`ClientMessage::Prove`].
+//! Only a proven address may reconnect to its seat.
+
+use std::{collections::VecDeque, str::FromStr};
+
+use ::rand::{thread_rng, Rng};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use snarkvm::prelude::{Address, Ciphertext, Field, Network, Plaintext, PrivateKey, Record, Request, Signature};
+
+use crate::table::{ErrorCode, Status};
+
+/// The protocol version spoken by this server.
+pub const PROTOCOL_VERSION: u32 = 3;
+/// The first version that understands encrypted records and commitments.
+pub const SEALED_RECORDS_VERSION: u32 = 2;
+/// The first version that proves the address with a signed challenge, needed to reconnect.
+pub const SIGNED_HANDSHAKE_VERSION: u32 = 3;
+/// The legacy, unversioned protocol.
+pub const LEGACY_VERSION: u32 = 0;
+
+/// How the server hands players the records their moves create.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RecordMode {
+    /// Records in the clear, the only mode clients before version 2 understand.
+    Plaintext,
+    /// Records encrypted to their owner, as they appear on chain.
+    #[default]
+    Encrypted,
+    /// Only the record commitments; clients find the records on chain once the move is confirmed.
+    Commitment,
+}
+
+impl FromStr for RecordMode {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> Result<Self> {
+        match mode {
+            "plaintext" => Ok(RecordMode::Plaintext),
+            "encrypted" => Ok(RecordMode::Encrypted),
+            "commitment" => Ok(RecordMode::Commitment),
+            _ => bail!("Record mode must be plaintext, encrypted or commitment"),
+        }
+    }
+}
+
+/// A record as sent in [`PlayerMessage::GameStatus`]. Each variant has its own
+/// string encoding, so plaintext records look the same as before version 2.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged, bound = "")]
+pub enum SealedRecord<N: Network> {
+    Plaintext(Record<N, Plaintext<N>>),
+    Ciphertext(Record<N, Ciphertext<N>>),
+    Commitment(Field<N>),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", bound = "")]
+pub enum ClientMessage<N: Network> {
+    /// Opens a connection and queues the player for a game.
+    Hello { version: u32, address: Address<N> },
+    /// The requests of one authorized battleship function call.
+    Move { requests: VecDeque<Request<N>> },
+    /// Gives up the current game.
+    Resign,
+    /// Answered with [`PlayerMessage::Pong`] carrying the same nonce.
+    Ping { nonce: u64 },
+    /// Relayed to both players of the game.
+    Chat { text: String },
+    /// Takes over the seat of `address` in a running game after a dropped connection.
+    Reconnect { version: u32, address: Address<N>, game_id: u64 },
+    /// Answers a [`PlayerMessage::Challenge`], see [`sign_challenge`].
+    Prove { signature: Signature<N> },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub enum PlayerMessage<N: Network> {
+    /// Handshake accepted, carries the version the server will speak.
+    Welcome(u32),
+    /// Sent before [`PlayerMessage::Welcome`] from version 3 on, to be signed by the hello's address.
+    Challenge(String),
+    /// The seat ("A" or "B") and the opponent's address.
+    Start(String, Address<N>),
+    /// The id of the game, needed to reconnect.
+    GameId(u64),
+    GameStatus(Status, Vec<SealedRecord<N>>),
+    TxID(N::TransactionID),
+    Pong(u64),
+    Chat(Address<N>, String),
+    Resigned(Address<N>),
+    Disconnected(Address<N>),
+    Reconnected(Address<N>),
+    /// The last message was rejected; the game continues.
+    Error(String),
+    /// The last move broke the battleship rules and was not evaluated; the game continues.
+    Rejected(ErrorCode, String),
+    /// The server is shutting down; the game will not continue.
+    Shutdown,
+}
+
+/// A fresh challenge for a handshake.
+pub fn new_challenge() -> String {
+    format!("{:032x}", thread_rng().gen::<u128>())
+}
+
+/// What is actually signed: the challenge with a prefix, so the signature is good for nothing else.
+fn challenge_message(challenge: &str) -> Vec<u8> {
+    format!("superbuffer-battleship:{challenge}").into_bytes()
+}
+
+pub fn sign_challenge<N: Network>(private_key: &PrivateKey<N>, challenge: &str) -> Result<Signature<N>> {
+    Signature::sign_bytes(private_key, &challenge_message(challenge), &mut thread_rng())
+}
+
+pub fn verify_challenge<N: Network>(address: &Address<N>, challenge: &str, signature: &Signature<N>) -> bool {
+    signature.verify_bytes(address, &challenge_message(challenge))
+}
+
+#[derive(Serialize)]
+struct Envelope<'a, N: Network> {
+    version: u32,
+    message: &'a PlayerMessage<N>,
+}
+
+#[derive(Deserialize)]
+#[serde(bound = "")]
+struct OwnedEnvelope<N: Network> {
+    version: u32,
+    message: PlayerMessage<N>,
+}
+
+pub fn is_supported(version: u32) -> bool {
+    version <= PROTOCOL_VERSION
+}
+
+/// Parses the first frame of a connection, accepting a bare address from legacy clients.
+pub fn decode_handshake<N: Network>(text: &str) -> Result<ClientMessage<N>> {
+    if let Ok(message) = serde_json::from_str::<ClientMessage<N>>(text) {
+        return match message {
+            ClientMessage::Hello { .. } | ClientMessage::Reconnect { .. } => Ok(message),
+            _ => bail!("Expected hello or reconnect"),
+        };
+    }
+    let address = Address::from_str(text.trim())?;
+    Ok(ClientMessage::Hello { version: LEGACY_VERSION, address })
+}
+
+pub fn decode_client_message<N: Network>(version: u32, text: &str) -> Result<ClientMessage<N>> {
+    if version == LEGACY_VERSION {
+        return Ok(ClientMessage::Move { requests: serde_json::from_str(text)? });
+    }
+    Ok(serde_json::from_str(text)?)
+}
+
+pub fn encode_player_message<N: Network>(version: u32, message: &PlayerMessage<N>) -> Result<String> {
+    if version == LEGACY_VERSION {
+        return Ok(serde_json::to_string(message)?);
+    }
+    Ok(serde_json::to_string(&Envelope { version, message })?)
+}
+
+/// Parses a server frame, returning the protocol version it was sent with.
+pub fn decode_player_message<N: Network>(text: &str) -> Result<(u32, PlayerMessage<N>)> {
+    if let Ok(envelope) = serde_json::from_str::<OwnedEnvelope<N>>(text) {
+        return Ok((envelope.version, envelope.message));
+    }
+    Ok((LEGACY_VERSION, serde_json::from_str(text)?))
+}
@@ -0,0 +1,201 @@
+//! Proof generation backends.
+//!
+//! The generator hands every authorization to a [`Prover`]: the in-process
+//! [`Executor`], a [`MockProver`] that proves nothing, or a [`RemoteProver`]
+//! that sends the work to prover workers over HTTP.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, ensure, Result};
+use snarkvm::prelude::{Authorization, ConsensusStorage, Execution, Network, Request, Transaction};
+use tracing::warn;
+
+use crate::executor::Executor;
+
+/// What a prover returns for an authorization.
+pub enum Proven<N: Network> {
+    /// A proven transaction, ready to broadcast.
+    Transaction(Box<Transaction<N>>),
+    /// A made-up id for an authorization that was never proven, nothing to broadcast.
+    Mock(N::TransactionID),
+}
+
+impl<N: Network> Proven<N> {
+    pub fn id(&self) -> N::TransactionID {
+        match self {
+            Proven::Transaction(transaction) => transaction.id(),
+            Proven::Mock(id) => *id,
+        }
+    }
+}
+
+pub trait Prover<N: Network>: Send + Sync {
+    fn prove(&self, authorization: Authorization<N>) -> Result<Proven<N>>;
+
+    /// How many authorizations the generator hands out at once.
+    fn parallelism(&self) -> usize {
+        1
+    }
+}
+
+impl<N: Network, C: ConsensusStorage<N>> Prover<N> for Executor<N, C> {
+    fn prove(&self, authorization: Authorization<N>) -> Result<Proven<N>> {
+        Ok(Proven::Transaction(Box::new(self.execute_no_fee(authorization)?)))
+    }
+}
+
+/// Answers every authorization with an id derived from its requests, for dry runs.
+pub struct MockProver;
+
+impl<N: Network> Prover<N> for MockProver {
+    fn prove(&self, authorization: Authorization<N>) -> Result<Proven<N>> {
+        let tvks = authorization.to_vec_deque().iter().map(|request| *request.tvk()).collect::<Vec<_>>();
+        Ok(Proven::Mock(N::TransactionID::from(N::hash_psd2(&tvks)?)))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub enum ProverConfig {
+    /// Prove in the server process.
+    #[default]
+    Local,
+    /// Prove nothing, see [`MockProver`].
+    Mock,
+    Remote(RemoteConfig),
+}
+
+#[derive(Clone, Debug)]
+pub struct RemoteConfig {
+    /// Base URLs of the prover workers, e.g. `http://10.0.0.2:4000`.
+    pub workers: Vec<String>,
+    /// How long a worker may take for one proof.
+    pub timeout: Duration,
+    /// Sent as `Authorization: Bearer <token>`, see the worker's `--token`.
+    pub token: Option<String>,
+}
+
+/// Sends authorizations to prover workers, taking turns between them and
+/// moving on to the next worker when one fails.
+///
+/// A worker accepts `POST /prove` with the requests of an authorization as a
+/// JSON array and answers with the JSON `Execution`, or 503 while all its
+/// proving slots are busy, or 401 without its bearer token. `GET /health`
+/// reports its `capacity`.
+pub struct RemoteProver {
+    agent: ureq::Agent,
+    token: Option<String>,
+    workers: Vec<String>,
+    next: Arc<AtomicUsize>,
+    /// Proving slots across all workers.
+    capacity: usize,
+    timeout: Duration,
+}
+
+/// How long to wait before asking again when every worker is busy.
+const BUSY_BACKOFF: Duration = Duration::from_secs(1);
+
+enum Attempt<N: Network> {
+    Proven(Box<Execution<N>>),
+    Busy,
+    Failed(anyhow::Error),
+}
+
+impl RemoteProver {
+    pub fn new(config: RemoteConfig) -> Result<Self> {
+        ensure!(!config.workers.is_empty(), "No prover workers configured");
+        let agent = ureq::AgentBuilder::new()
+            .timeout(config.timeout)
+            .user_agent(concat!("superbuffer/", env!("CARGO_PKG_VERSION")))
+            .build();
+        let mut prover = Self {
+            agent,
+            token: config.token,
+            workers: config.workers,
+            next: Default::default(),
+            capacity: 0,
+            timeout: config.timeout,
+        };
+        prover.capacity = prover.workers.iter().map(|worker| prover.capacity(worker)).sum();
+        Ok(prover)
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        let request = self.agent.request(method, url);
+        match &self.token {
+            Some(token) => request.set("Authorization", &format!("Bearer {token}")),
+            None => request,
+        }
+    }
+
+    /// The proving slots `worker` reports, one if it cannot be reached yet.
+    fn capacity(&self, worker: &str) -> usize {
+        let url = format!("{}/health", worker.trim_end_matches('/'));
+        let health = self.request("GET", &url).call().map_err(anyhow::Error::from).and_then(|response| {
+            let health: serde_json::Value = response.into_json()?;
+            health["capacity"].as_u64().ok_or_else(|| anyhow!("no capacity in {health}"))
+        });
+        match health {
+            Ok(capacity) => capacity as usize,
+            Err(err) => {
+                warn!("prover worker {}: {}", url, err);
+                1
+            }
+        }
+    }
+
+    fn prove_on<N: Network>(&self, worker: &str, requests: &[Request<N>]) -> Attempt<N> {
+        let url = format!("{}/prove", worker.trim_end_matches('/'));
+        match self.request("POST", &url).send_json(requests) {
+            Ok(response) => match response.into_json() {
+                Ok(execution) => Attempt::Proven(Box::new(execution)),
+                Err(err) => Attempt::Failed(anyhow!("{url}: {err}")),
+            },
+            Err(ureq::Error::Status(503, _)) => Attempt::Busy,
+            Err(ureq::Error::Status(status, response)) => {
+                Attempt::Failed(anyhow!("{url}: {status} {}", response.into_string().unwrap_or_default()))
+            }
+            // Transport errors name the URL already.
+            Err(err) => Attempt::Failed(err.into()),
+        }
+    }
+}
+
+impl<N: Network> Prover<N> for RemoteProver {
+    fn prove(&self, authorization: Authorization<N>) -> Result<Proven<N>> {
+        let requests = authorization.to_vec_deque().into_iter().collect::<Vec<_>>();
+        let give_up = Instant::now() + self.timeout;
+        loop {
+            let start = self.next.fetch_add(1, Ordering::Relaxed);
+            let mut busy = false;
+            let mut last_error = None;
+            for index in (start..start + self.workers.len()).map(|index| index % self.workers.len()) {
+                match self.prove_on::<N>(&self.workers[index], &requests) {
+                    Attempt::Proven(execution) => {
+                        return Ok(Proven::Transaction(Box::new(Transaction::from_execution(*execution, None)?)))
+                    }
+                    Attempt::Busy => busy = true,
+                    Attempt::Failed(err) => {
+                        warn!("prover worker: {}", err);
+                        last_error = Some(err);
+                    }
+                }
+            }
+            // A busy worker will free up, a failing one is not worth waiting for.
+            if !busy || Instant::now() >= give_up {
+                return Err(last_error.unwrap_or_else(|| anyhow!("All prover workers are busy")));
+            }
+            thread::sleep(BUSY_BACKOFF);
+        }
+    }
+
+    fn parallelism(&self) -> usize {
+        self.capacity
+    }
+}
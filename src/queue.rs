@@ -0,0 +1,396 @@
+//! The queue of moves waiting for a proof.
+//!
+//! Jobs are served by priority instead of arrival: every battleship function
+//! has a base priority, moves of priority addresses get a bonus and a job
+//! gains a level for every `aging` interval it waits, so nothing starves.
+//! Moves of one game are proven one at a time and in order, a move needs the
+//! records of the one before: only the oldest job of each game whose previous
+//! move is not being proven is up for selection. Between equal priorities the game served least recently
+//! goes first, then the older game.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+use snarkvm::{prelude::{Address, FromStr, Network}, synthesizer::Authorization};
+use tokio::sync::oneshot;
+
+use crate::{generator::ProofResult, metrics::METRICS};
+
+#[derive(Clone, Debug)]
+pub struct QueueConfig {
+    /// Base priority of each battleship function, higher is proven first. Others get 0.
+    pub priorities: HashMap<String, u32>,
+    /// Callers whose moves get `priority_bonus` on top, e.g. paying players.
+    pub priority_addresses: Vec<String>,
+    pub priority_bonus: u32,
+    /// A waiting job gains a priority level per interval.
+    pub aging: Duration,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        // Moves of running games first, new boards last.
+        let priorities = [("play", 3), ("start_battleship", 2), ("offer_battleship", 2), ("initialize_board", 1)];
+        Self {
+            priorities: priorities.into_iter().map(|(function, priority)| (function.to_string(), priority)).collect(),
+            priority_addresses: vec![],
+            priority_bonus: 2,
+            aging: Duration::from_secs(60),
+        }
+    }
+}
+
+/// What the queue knows about a job, as served by `/api/queue`.
+#[derive(Clone, Debug, Serialize)]
+#[serde(bound = "")]
+pub struct JobInfo<N: Network> {
+    pub id: u64,
+    /// `None` for requests persisted by an earlier run.
+    pub game_id: Option<u64>,
+    pub function: String,
+    pub caller: Address<N>,
+    /// Base priority and bonus, without aging.
+    pub priority: u32,
+    #[serde(skip)]
+    enqueued: Instant,
+}
+
+pub struct ProofJob<N: Network> {
+    pub info: JobInfo<N>,
+    pub authorization: Authorization<N>,
+    pub notify: oneshot::Sender<ProofResult<N>>,
+}
+
+#[derive(Serialize)]
+#[serde(bound = "")]
+pub struct QueuedJob<N: Network> {
+    #[serde(flatten)]
+    pub info: JobInfo<N>,
+    /// Priority including aging, what the job is ranked by right now.
+    pub effective_priority: u32,
+    pub waiting_secs: u64,
+}
+
+#[derive(Serialize)]
+#[serde(bound = "")]
+pub struct QueueSnapshot<N: Network> {
+    pub queued: usize,
+    pub proving: Vec<JobInfo<N>>,
+    pub by_function: HashMap<String, usize>,
+    /// Queued jobs, highest effective priority first.
+    pub jobs: Vec<QueuedJob<N>>,
+}
+
+struct State<N: Network> {
+    /// Queued jobs per game, oldest first.
+    games: HashMap<Option<u64>, VecDeque<ProofJob<N>>>,
+    proving: HashMap<u64, JobInfo<N>>,
+    /// When each game last had a job taken, in `pops`. Kept until the game is over.
+    last_served: HashMap<Option<u64>, u64>,
+    /// Games that are over but still have jobs queued.
+    finished: HashSet<u64>,
+    pops: u64,
+    next_id: u64,
+    closed: bool,
+}
+
+/// Shared between the games, which push moves, and the generator threads, which pop them.
+#[derive(Clone)]
+pub struct ProofQueue<N: Network> {
+    config: Arc<QueueConfig>,
+    priority_addresses: Arc<HashSet<Address<N>>>,
+    state: Arc<Mutex<State<N>>>,
+    available: Arc<Condvar>,
+}
+
+impl<N: Network> ProofQueue<N> {
+    pub fn new(config: QueueConfig) -> Result<Self> {
+        let priority_addresses =
+            config.priority_addresses.iter().map(|address| Address::from_str(address)).collect::<Result<_>>()?;
+        let state = State {
+            games: HashMap::new(),
+            proving: HashMap::new(),
+            last_served: HashMap::new(),
+            finished: HashSet::new(),
+            pops: 0,
+            next_id: 0,
+            closed: false,
+        };
+        Ok(Self {
+            config: Arc::new(config),
+            priority_addresses: Arc::new(priority_addresses),
+            state: Arc::new(Mutex::new(state)),
+            available: Default::default(),
+        })
+    }
+
+    /// Queues a move of `game_id` for proving, `notify` receives the outcome.
+    pub fn push(
+        &self,
+        game_id: Option<u64>,
+        authorization: Authorization<N>,
+        notify: oneshot::Sender<ProofResult<N>>,
+    ) -> Result<()> {
+        let Some(request) = authorization.to_vec_deque().front().cloned() else {
+            bail!("Empty authorization");
+        };
+        let function = request.function_name().to_string();
+        let bonus = if self.priority_addresses.contains(request.caller()) { self.config.priority_bonus } else { 0 };
+        let priority = self.config.priorities.get(&function).copied().unwrap_or_default() + bonus;
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            bail!("proof generator stopped");
+        }
+        let info = JobInfo { id: state.next_id, game_id, function, caller: *request.caller(), priority, enqueued: Instant::now() };
+        state.next_id += 1;
+        state.games.entry(game_id).or_default().push_back(ProofJob { info, authorization, notify });
+        METRICS.prover_queue_depth.inc();
+        self.available.notify_one();
+        Ok(())
+    }
+
+    /// Takes the next job, waiting for one. `None` once the queue is closed and empty.
+    pub fn pop(&self) -> Option<ProofJob<N>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(game_id) = self.next_game(&state) {
+                let jobs = state.games.get_mut(&game_id).expect("next game has jobs");
+                let job = jobs.pop_front().expect("next game has jobs");
+                if jobs.is_empty() {
+                    state.games.remove(&game_id);
+                }
+                // Remembered while the game waits for its next move, or it would rank as never served.
+                state.pops += 1;
+                let pops = state.pops;
+                state.last_served.insert(game_id, pops);
+                if let Some(id) = game_id.filter(|id| state.finished.contains(id) && !state.games.contains_key(&Some(*id))) {
+                    state.finished.remove(&id);
+                    state.last_served.remove(&Some(id));
+                }
+                state.proving.insert(job.info.id, job.info.clone());
+                METRICS.prover_queue_depth.dec();
+                return Some(job);
+            }
+            // Jobs held back behind a running proof still have to be drained.
+            if state.closed && state.games.is_empty() {
+                return None;
+            }
+            state = self.available.wait(state).unwrap();
+        }
+    }
+
+    /// Marks a job taken with [`Self::pop`] as done.
+    pub fn finish(&self, id: u64) {
+        self.state.lock().unwrap().proving.remove(&id);
+        // The next move of the game may be waiting for this one.
+        self.available.notify_all();
+    }
+
+    /// Forgets `game_id` once it is over and its queued jobs are taken.
+    pub fn finish_game(&self, game_id: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.games.contains_key(&Some(game_id)) {
+            state.finished.insert(game_id);
+        } else {
+            state.last_served.remove(&Some(game_id));
+        }
+    }
+
+    /// Wakes up the generator threads; they drain what is queued and stop.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.available.notify_all();
+    }
+
+    pub fn snapshot(&self) -> QueueSnapshot<N> {
+        let state = self.state.lock().unwrap();
+        let mut jobs = state
+            .games
+            .values()
+            .flatten()
+            .map(|job| QueuedJob {
+                info: job.info.clone(),
+                effective_priority: self.effective_priority(&job.info),
+                waiting_secs: job.info.enqueued.elapsed().as_secs(),
+            })
+            .collect::<Vec<_>>();
+        jobs.sort_by_key(|job| (std::cmp::Reverse(job.effective_priority), job.info.id));
+        let mut by_function = HashMap::new();
+        for job in &jobs {
+            *by_function.entry(job.info.function.clone()).or_default() += 1;
+        }
+        let mut proving = state.proving.values().cloned().collect::<Vec<_>>();
+        proving.sort_by_key(|job| job.id);
+        QueueSnapshot { queued: jobs.len(), proving, by_function, jobs }
+    }
+
+    fn effective_priority(&self, info: &JobInfo<N>) -> u32 {
+        let aging = self.config.aging.as_secs().max(1);
+        info.priority + (info.enqueued.elapsed().as_secs() / aging) as u32
+    }
+
+    /// The game whose oldest job goes next.
+    fn next_game(&self, state: &State<N>) -> Option<Option<u64>> {
+        state
+            .games
+            .iter()
+            .filter(|(game_id, _)| {
+                game_id.is_none() || !state.proving.values().any(|proving| proving.game_id == **game_id)
+            })
+            .filter_map(|(game_id, jobs)| jobs.front().map(|job| (game_id, job)))
+            .min_by_key(|(game_id, job)| {
+                (
+                    std::cmp::Reverse(self.effective_priority(&job.info)),
+                    state.last_served.get(*game_id).copied().unwrap_or_default(),
+                    // Game ids grow, a smaller one is an older game. Persisted requests come first.
+                    game_id.map_or(0, |id| id + 1),
+                    job.info.id,
+                )
+            })
+            .map(|(game_id, _)| *game_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use snarkvm::prelude::{Identifier, PrivateKey, ProgramID, Request, Testnet3, Value};
+
+    type N = Testnet3;
+
+    fn authorization(private_key: &PrivateKey<N>, function: &str) -> Authorization<N> {
+        let rng = &mut ::rand::thread_rng();
+        let program_id = ProgramID::from_str("battleship.aleo").unwrap();
+        let function = Identifier::from_str(function).unwrap();
+        let request = Request::sign(private_key, program_id, function, std::iter::empty::<Value<N>>(), &[], rng).unwrap();
+        Authorization::new(&[request])
+    }
+
+    struct Harness {
+        queue: ProofQueue<N>,
+        private_key: PrivateKey<N>,
+    }
+
+    impl Harness {
+        fn new(config: QueueConfig) -> Self {
+            let private_key = PrivateKey::new(&mut ::rand::thread_rng()).unwrap();
+            Self { queue: ProofQueue::new(config).unwrap(), private_key }
+        }
+
+        fn push(&self, game_id: u64, function: &str) {
+            self.push_as(&self.private_key, game_id, function);
+        }
+
+        fn push_as(&self, private_key: &PrivateKey<N>, game_id: u64, function: &str) {
+            let (notify, _) = oneshot::channel();
+            self.queue.push(Some(game_id), authorization(private_key, function), notify).unwrap();
+        }
+
+        /// Takes and finishes the next job, `None` if nothing can be taken right now.
+        fn take(&self) -> Option<(Option<u64>, String)> {
+            let job = self.take_unfinished()?;
+            self.queue.finish(job.info.id);
+            Some((job.info.game_id, job.info.function))
+        }
+
+        fn take_unfinished(&self) -> Option<ProofJob<N>> {
+            // `pop` blocks on an empty queue, only call it when a game is ready.
+            self.queue.next_game(&self.queue.state.lock().unwrap())?;
+            self.queue.pop()
+        }
+    }
+
+    #[test]
+    fn higher_priority_first() {
+        let harness = Harness::new(QueueConfig::default());
+        harness.push(1, "initialize_board");
+        harness.push(2, "offer_battleship");
+        harness.push(3, "play");
+        assert_eq!(harness.take(), Some((Some(3), "play".to_string())));
+        assert_eq!(harness.take(), Some((Some(2), "offer_battleship".to_string())));
+        assert_eq!(harness.take(), Some((Some(1), "initialize_board".to_string())));
+        assert_eq!(harness.take(), None);
+    }
+
+    #[test]
+    fn priority_addresses_get_a_bonus() {
+        let vip = PrivateKey::<N>::new(&mut ::rand::thread_rng()).unwrap();
+        let harness = Harness::new(QueueConfig {
+            priority_addresses: vec![Address::try_from(&vip).unwrap().to_string()],
+            priority_bonus: 3,
+            ..Default::default()
+        });
+        harness.push(1, "play");
+        harness.push_as(&vip, 2, "initialize_board");
+        assert_eq!(harness.take(), Some((Some(2), "initialize_board".to_string())));
+        assert_eq!(harness.take(), Some((Some(1), "play".to_string())));
+    }
+
+    #[test]
+    fn moves_of_a_game_are_proven_one_at_a_time() {
+        let harness = Harness::new(QueueConfig::default());
+        harness.push(1, "play");
+        harness.push(1, "play");
+        harness.push(2, "initialize_board");
+        let first = harness.take_unfinished().unwrap();
+        assert_eq!(first.info.game_id, Some(1));
+        // The second move of game 1 waits for the first, even though it ranks higher.
+        assert_eq!(harness.take(), Some((Some(2), "initialize_board".to_string())));
+        assert_eq!(harness.take(), None);
+        harness.queue.finish(first.info.id);
+        assert_eq!(harness.take(), Some((Some(1), "play".to_string())));
+    }
+
+    #[test]
+    fn equal_levels_alternate_between_games() {
+        let harness = Harness::new(QueueConfig::default());
+        harness.push(1, "play");
+        harness.push(2, "play");
+        let mut served = vec![];
+        for _ in 0..4 {
+            let (game_id, _) = harness.take().unwrap();
+            served.push(game_id.unwrap());
+            // Each game has one move queued at a time, the next follows once the last is proven.
+            harness.push(game_id.unwrap(), "play");
+        }
+        assert_eq!(served, vec![1, 2, 1, 2]);
+    }
+
+    #[test]
+    fn finished_games_are_forgotten() {
+        let harness = Harness::new(QueueConfig::default());
+        harness.push(1, "play");
+        harness.take().unwrap();
+        harness.queue.finish_game(1);
+        assert!(harness.queue.state.lock().unwrap().last_served.is_empty());
+    }
+
+    #[test]
+    fn waiting_jobs_age() {
+        let harness = Harness::new(QueueConfig { aging: Duration::from_secs(60), ..Default::default() });
+        harness.push(1, "initialize_board");
+        // Waited three intervals, level 1 + 3 beats a fresh play move at 3.
+        for job in harness.queue.state.lock().unwrap().games.values_mut().flatten() {
+            job.info.enqueued -= Duration::from_secs(180);
+        }
+        harness.push(2, "play");
+        assert_eq!(harness.take(), Some((Some(1), "initialize_board".to_string())));
+        assert_eq!(harness.take(), Some((Some(2), "play".to_string())));
+    }
+
+    #[test]
+    fn closed_queue_drains() {
+        let harness = Harness::new(QueueConfig::default());
+        harness.push(1, "play");
+        harness.queue.close();
+        assert!(harness.queue.pop().is_some());
+        assert!(harness.queue.pop().is_none());
+        let (notify, _) = oneshot::channel();
+        assert!(harness.queue.push(Some(2), authorization(&harness.private_key, "play"), notify).is_err());
+    }
+}
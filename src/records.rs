@@ -0,0 +1,240 @@
+//! Sealing of the records a move creates, and their recovery from the chain.
+//!
+//! The server hands players their records as soon as a move is evaluated,
+//! sealed by its [`RecordMode`](crate::protocol::RecordMode): encrypted to the
+//! owner as they will appear on chain (the default), as commitments only, or
+//! in plaintext for clients before protocol version 2. [`record_randomizer`]
+//! derives the nonce the VM gives each record, so the encrypted record matches
+//! the one in the transaction. A player that misses the message, or only got
+//! commitments, can still find the records on chain: [`RecordScanner`] walks the blocks of a [`BlockSource`], decrypts every
+//! record owned by its view key and marks records spent when their serial
+//! number shows up as a transition input.
+
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::Context;
+use snarkvm::prelude::*;
+
+use crate::node::NodeClient;
+
+/// The most blocks the node's `blocks` endpoint returns per call.
+const BLOCKS_PER_REQUEST: u32 = 50;
+
+/// Somewhere to read blocks from.
+pub trait BlockSource<N: Network> {
+    fn latest_height(&self) -> Result<u32>;
+
+    /// Blocks `start..end`, in order.
+    fn blocks(&self, start: u32, end: u32) -> Result<Vec<Block<N>>>;
+}
+
+/// Reads blocks from a node's REST API.
+pub struct RestBlocks {
+    node: NodeClient,
+}
+
+impl RestBlocks {
+    pub fn new(node: NodeClient) -> Self {
+        Self { node }
+    }
+}
+
+impl<N: Network> BlockSource<N> for RestBlocks {
+    fn latest_height(&self) -> Result<u32> {
+        Ok(self.node.get::<N, _>("latest/height")?)
+    }
+
+    fn blocks(&self, start: u32, end: u32) -> Result<Vec<Block<N>>> {
+        let mut blocks = Vec::with_capacity(end.saturating_sub(start) as usize);
+        let mut from = start;
+        while from < end {
+            let to = end.min(from + BLOCKS_PER_REQUEST);
+            blocks.extend(self.node.get::<N, Vec<Block<N>>>(&format!("blocks?start={from}&end={to}"))?);
+            from = to;
+        }
+        Ok(blocks)
+    }
+}
+
+/// Blocks held in memory, a stand-in for a ledger when there is no node to ask.
+#[derive(Default)]
+pub struct LocalBlocks<N: Network> {
+    blocks: Vec<Block<N>>,
+}
+
+impl<N: Network> LocalBlocks<N> {
+    pub fn new(mut blocks: Vec<Block<N>>) -> Self {
+        blocks.sort_by_key(|block| block.height());
+        Self { blocks }
+    }
+
+    /// Reads every `*.json` block in `dir`.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let mut blocks = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "json") {
+                let json = fs::read_to_string(&path)?;
+                blocks.push(serde_json::from_str(&json).with_context(|| format!("parse {}", path.display()))?);
+            }
+        }
+        Ok(Self::new(blocks))
+    }
+
+    pub fn push(&mut self, block: Block<N>) {
+        self.blocks.push(block);
+        self.blocks.sort_by_key(|block| block.height());
+    }
+}
+
+impl<N: Network> BlockSource<N> for LocalBlocks<N> {
+    fn latest_height(&self) -> Result<u32> {
+        self.blocks.last().map(|block| block.height()).ok_or_else(|| anyhow!("No blocks"))
+    }
+
+    fn blocks(&self, start: u32, end: u32) -> Result<Vec<Block<N>>> {
+        Ok(self.blocks.iter().filter(|block| (start..end).contains(&block.height())).cloned().collect())
+    }
+}
+
+/// A decrypted record owned by the scanner's account.
+#[derive(Clone, Debug)]
+pub struct OwnedRecord<N: Network> {
+    pub commitment: Field<N>,
+    pub serial_number: Field<N>,
+    pub record: Record<N, Plaintext<N>>,
+    pub program_id: ProgramID<N>,
+    pub transaction_id: N::TransactionID,
+    pub height: u32,
+    /// The height of the block that consumed the record, if any.
+    pub spent_at: Option<u32>,
+}
+
+impl<N: Network> OwnedRecord<N> {
+    pub fn is_spent(&self) -> bool {
+        self.spent_at.is_some()
+    }
+}
+
+/// Keeps the records of one account up to date with the blocks it has scanned.
+pub struct RecordScanner<N: Network> {
+    private_key: PrivateKey<N>,
+    view_key: ViewKey<N>,
+    /// The next height to scan.
+    height: u32,
+    records: HashMap<Field<N>, OwnedRecord<N>>,
+    /// Serial number to commitment, for every record found.
+    serial_numbers: HashMap<Field<N>, Field<N>>,
+}
+
+impl<N: Network> RecordScanner<N> {
+    pub fn new(private_key: PrivateKey<N>) -> Result<Self> {
+        Ok(Self {
+            private_key,
+            view_key: ViewKey::try_from(&private_key)?,
+            height: 0,
+            records: HashMap::new(),
+            serial_numbers: HashMap::new(),
+        })
+    }
+
+    /// Starts scanning at `height` instead of genesis.
+    pub fn starting_at(mut self, height: u32) -> Self {
+        self.height = height;
+        self
+    }
+
+    pub fn view_key(&self) -> &ViewKey<N> {
+        &self.view_key
+    }
+
+    /// The next height [`Self::sync`] will scan.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Scans every block up to the source's latest height, returning how many new records were found.
+    pub fn sync(&mut self, source: &impl BlockSource<N>) -> Result<usize> {
+        let latest = source.latest_height()?;
+        let before = self.records.len();
+        while self.height <= latest {
+            let end = (latest + 1).min(self.height + BLOCKS_PER_REQUEST);
+            for block in source.blocks(self.height, end)? {
+                self.scan_block(&block)?;
+            }
+            self.height = end;
+        }
+        Ok(self.records.len() - before)
+    }
+
+    pub fn scan_block(&mut self, block: &Block<N>) -> Result<()> {
+        for transaction in block.transactions().iter() {
+            self.scan_transaction(block.height(), transaction)?;
+        }
+        Ok(())
+    }
+
+    /// Picks up the records `transaction` creates for this account and marks the ones it consumes as spent.
+    /// Transactions have to be scanned in chain order.
+    pub fn scan_transaction(&mut self, height: u32, transaction: &Transaction<N>) -> Result<()> {
+        for transition in transaction.transitions() {
+            for serial_number in transition.serial_numbers() {
+                let record = self.serial_numbers.get(serial_number).and_then(|commitment| self.records.get_mut(commitment));
+                if let Some(record) = record {
+                    record.spent_at = Some(height);
+                }
+            }
+            for (commitment, record) in transition.records() {
+                if self.records.contains_key(commitment) || !record.is_owner(&self.view_key) {
+                    continue;
+                }
+                let serial_number = Record::<N, Plaintext<N>>::serial_number(self.private_key, *commitment)?;
+                self.serial_numbers.insert(serial_number, *commitment);
+                self.records.insert(
+                    *commitment,
+                    OwnedRecord {
+                        commitment: *commitment,
+                        serial_number,
+                        record: record.decrypt(&self.view_key)?,
+                        program_id: *transition.program_id(),
+                        transaction_id: transaction.id(),
+                        height,
+                        spent_at: None,
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the record with this commitment has been found.
+    pub fn contains(&self, commitment: &Field<N>) -> bool {
+        self.records.contains_key(commitment)
+    }
+
+    /// Every record found so far, oldest first.
+    pub fn records(&self) -> Vec<&OwnedRecord<N>> {
+        let mut records = self.records.values().collect::<Vec<_>>();
+        records.sort_by_key(|record| record.height);
+        records
+    }
+
+    pub fn unspent(&self) -> impl Iterator<Item = &OwnedRecord<N>> {
+        self.records().into_iter().filter(|record| !record.is_spent())
+    }
+
+    pub fn spent(&self) -> impl Iterator<Item = &OwnedRecord<N>> {
+        self.records().into_iter().filter(|record| record.is_spent())
+    }
+
+    /// Unspent records of `program_id`, e.g. the `board_state` and `move` records of `battleship.aleo`.
+    pub fn unspent_for(&self, program_id: &ProgramID<N>) -> Vec<Record<N, Plaintext<N>>> {
+        self.unspent().filter(|record| record.program_id == *program_id).map(|record| record.record.clone()).collect()
+    }
+}
+
+/// The randomizer snarkVM encrypts an output record of `request` with: the hash of the transition
+/// view key and the locator of the register the function outputs the record from.
+pub fn record_randomizer<N: Network>(request: &Request<N>, register: &Register<N>) -> Result<Scalar<N>> {
+    N::hash_to_scalar_psd2(&[*request.tvk(), Field::from_u64(register.locator())])
+}
@@ -27,7 +27,7 @@ pub fn action_from_request<N: Network>(request: Request<N>) -> Result<ActionRequ
         "offer_battleship" => ActionRequest::Offer(request),
         "start_battleship" => ActionRequest::Start(request),
         "play" => ActionRequest::Play(request),
-        _ => unreachable!(),
+        _ => bail!("Unknown function {function_name}"),
     };
     Ok(action)
 }
@@ -1,138 +1,685 @@
-use std::{net::SocketAddr, time::Duration};
+use std::{collections::HashMap, fs, net::{IpAddr, SocketAddr}, path::PathBuf, sync::{Arc, Mutex as StdMutex}, time::{Duration, SystemTime}};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use axum::{
     extract::{
         ws::{Message, WebSocket},
-        State, WebSocketUpgrade,
+        ConnectInfo, FromRef, State, WebSocketUpgrade,
     },
+    http::{header, StatusCode},
     response::IntoResponse,
     routing::get,
     Router,
 };
+use axum_server::{tls_rustls::RustlsConfig, Handle};
 use snarkvm::{prelude::*, synthesizer::helpers::memory::ConsensusMemory};
-use tokio::{sync::{mpsc::{self, Sender}, oneshot}, time::sleep};
+use tokio::{sync::{mpsc::{self, Sender}, oneshot, watch}, time::{sleep_until, Instant}};
 use tracing::*;
 
-use crate::{executor::Executor, player::Player, requests::action_from_request, table::ChessTable, generator::{start_generator, ProofRequest}};
+use crate::{api, broadcast::{BroadcastConfig, Broadcaster}, executor::Executor, games::GameRegistry, limits::{Limits, LimitsConfig}, node::{NodeClient, NodeConfig}, player::{KeepAlive, Player}, prover::{MockProver, Prover, ProverConfig, RemoteProver}, protocol::{decode_handshake, encode_player_message, is_supported, new_challenge, verify_challenge, ClientMessage, PlayerMessage, RecordMode, SealedRecord, LEGACY_VERSION, PROTOCOL_VERSION, SEALED_RECORDS_VERSION, SIGNED_HANDSHAKE_VERSION}, requests::action_from_request, table::{ChessTable, ErrorCode}, generator::{load_pending, start_generator, Deadline, ProofResult}, queue::{ProofQueue, QueueConfig}, metrics::{GaugeGuard, METRICS}};
 
-pub async fn run<N: Network, A: snarkvm::circuit::Aleo<Network = N>>(base: &str) {
-    let query = Query::from(base);
-    let storage = ConsensusMemory::open(None).unwrap();
-    let executor = Executor::<N, _>::new(storage, query).unwrap();
-    let tx = run_server::<N, A>(executor).await;
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    pub node: NodeConfig,
+    pub broadcast: BroadcastConfig,
+    pub queue: QueueConfig,
+    /// How long queued proofs may keep running after a shutdown signal.
+    pub shutdown_timeout: Duration,
+    /// Where unproven requests are persisted on shutdown and reloaded on start.
+    pub pending_dir: PathBuf,
+    pub keepalive: KeepAlive,
+    /// How players receive their records, encrypted unless configured otherwise.
+    pub records: RecordMode,
+    /// Serve `https://` and `wss://` instead of plain HTTP.
+    pub tls: Option<TlsConfig>,
+    /// Where moves are proven. [`ProverConfig::Mock`] only evaluates them, players get made-up transaction ids.
+    pub prover: ProverConfig,
+    /// Load the program from this directory instead of the node.
+    pub program_dir: Option<PathBuf>,
+    /// Where proving keys are kept between restarts, they are synthesized on every start without one.
+    pub key_cache: Option<PathBuf>,
+    pub limits: LimitsConfig,
+}
+
+/// PEM encoded certificate chain and private key. Both files are watched and
+/// reloaded when they change, so renewed certificates need no restart.
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl TlsConfig {
+    fn modified(&self) -> (Option<SystemTime>, Option<SystemTime>) {
+        let modified = |path: &PathBuf| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+        (modified(&self.cert), modified(&self.key))
+    }
+}
+
+/// How often broadcast transactions are checked for a rebroadcast.
+const REBROADCAST_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often the certificate files are checked for changes.
+const CERT_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a game waits for a dropped player to reconnect.
+const RECONNECT_GRACE: Duration = Duration::from_secs(60);
+
+/// How long a client has to answer the handshake challenge.
+const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The address, protocol version, new socket and IP of a reconnecting player, proven by the handshake.
+type Reconnect<N> = (Address<N>, u32, WebSocket, IpAddr);
+
+/// Routes reconnecting players to the game holding their seat.
+#[derive(Clone)]
+pub struct Seats<N: Network> {
+    games: Arc<StdMutex<HashMap<u64, Sender<Reconnect<N>>>>>,
+}
+
+impl<N: Network> Default for Seats<N> {
+    fn default() -> Self {
+        Self { games: Default::default() }
+    }
+}
+
+impl<N: Network> Seats<N> {
+    fn register(&self, id: u64) -> mpsc::Receiver<Reconnect<N>> {
+        let (tx, rx) = mpsc::channel(4);
+        self.games.lock().unwrap().insert(id, tx);
+        rx
+    }
+
+    fn remove(&self, id: u64) {
+        self.games.lock().unwrap().remove(&id);
+    }
+
+    async fn route(&self, id: u64, reconnect: Reconnect<N>) -> Result<()> {
+        let tx = self.games.lock().unwrap().get(&id).cloned();
+        match tx {
+            Some(tx) => tx.send(reconnect).await.map_err(|_| anyhow!("Game {id} is over")),
+            None => bail!("Game {id} not found"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AppState<N: Network> {
+    players: Sender<Player<N>>,
+    games: GameRegistry<N>,
+    seats: Seats<N>,
+    keepalive: KeepAlive,
+    records: RecordMode,
+    broadcaster: Broadcaster<N>,
+    queue: ProofQueue<N>,
+    limits: Limits<N>,
+}
+
+impl<N: Network> FromRef<AppState<N>> for GameRegistry<N> {
+    fn from_ref(state: &AppState<N>) -> Self {
+        state.games.clone()
+    }
+}
+
+impl<N: Network> FromRef<AppState<N>> for Broadcaster<N> {
+    fn from_ref(state: &AppState<N>) -> Self {
+        state.broadcaster.clone()
+    }
+}
+
+impl<N: Network> FromRef<AppState<N>> for ProofQueue<N> {
+    fn from_ref(state: &AppState<N>) -> Self {
+        state.queue.clone()
+    }
+}
+
+pub async fn run<N: Network, A: snarkvm::circuit::Aleo<Network = N>>(config: ServerConfig) -> Result<()> {
+    let node = NodeClient::new(config.node.clone())?;
+    let storage = ConsensusMemory::open(None)?;
+    let broadcaster = Broadcaster::<N>::new(node.clone(), config.broadcast.clone())?;
+    let executor = match &config.program_dir {
+        Some(dir) => Executor::<N, _>::from_program_dir(storage, node, dir)?,
+        None => Executor::<N, _>::new(storage, node)?,
+    };
+
+    let dry_run = matches!(config.prover, ProverConfig::Mock);
+    // Proving in process needs the proving keys, checking remote proofs of programs loaded
+    // from source needs the verifying keys, which only synthesis provides.
+    let local = matches!(config.prover, ProverConfig::Local);
+    if local || (!dry_run && config.program_dir.is_some()) {
+        info!("Preparing proving keys");
+        let start = std::time::Instant::now();
+        executor.prewarm::<A>(config.key_cache.as_deref()).context("prepare proving keys")?;
+        info!("Proving keys ready in {:.1?}", start.elapsed());
+    }
+    let prover: Box<dyn Prover<N>> = match config.prover.clone() {
+        ProverConfig::Local => Box::new(executor.clone()),
+        ProverConfig::Mock => {
+            warn!("Dry run, moves are evaluated but never proven or broadcast");
+            Box::new(MockProver)
+        }
+        ProverConfig::Remote(remote) => Box::new(RemoteProver::new(remote)?),
+    };
+    let deadline = Deadline::default();
+    // A dry run neither persists nor picks up pending proofs, they are left for a real run.
+    let pending_dir = (!dry_run).then(|| config.pending_dir.clone());
+    let queue = ProofQueue::<N>::new(config.queue.clone())?;
+    let generator =
+        start_generator(queue.clone(), prover, executor.clone(), broadcaster.clone(), deadline.clone(), pending_dir);
+    let pending = if dry_run { Ok(vec![]) } else { load_pending::<N>(&config.pending_dir) };
+    match pending {
+        Ok(authorizations) => {
+            info!("Requeue {} pending proofs", authorizations.len());
+            for authorization in authorizations {
+                // Nobody is waiting on these any more, the generator only has to broadcast them.
+                let (transaction_tx, _) = oneshot::channel();
+                let _ = queue.push(None, authorization, transaction_tx);
+            }
+        }
+        Err(err) => error!("load pending proofs: {}", err),
+    }
+
+    let limits = Limits::<N>::new(config.limits.clone())?;
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let games = GameRegistry::default();
+    let seats = Seats::default();
+    let players = run_server::<N, A>(executor, queue.clone(), games.clone(), seats.clone(), config.records, limits.clone(), shutdown_rx.clone()).await;
+    tokio::spawn(rebroadcast(broadcaster.clone(), shutdown_rx));
 
     let app = Router::new()
         .route("/battleship", get(ws_handler))
-        .with_state(tx);
+        .route("/metrics", get(metrics_handler))
+        .nest("/api", api::routes())
+        .with_state(AppState { players, games, seats, keepalive: config.keepalive, records: config.records, broadcaster, queue: queue.clone(), limits });
 
-    axum::Server::bind(&SocketAddr::from_str("0.0.0.0:3000").unwrap())
-        .serve(app.into_make_service())
-        .await
-        .expect("couldn't start rest server");
+    let addr = SocketAddr::from_str("0.0.0.0:3000").unwrap();
+    match config.tls.clone() {
+        None => axum::Server::try_bind(&addr)
+            .with_context(|| format!("bind {addr}"))?
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown(shutdown_signal())
+            .await
+            .context("serve")?,
+        Some(tls) => {
+            // One listener for the game socket and the REST routes alike.
+            let rustls = RustlsConfig::from_pem_file(&tls.cert, &tls.key)
+                .await
+                .with_context(|| format!("load TLS certificate {} and key {}", tls.cert.display(), tls.key.display()))?;
+            tokio::spawn(reload_certificates(rustls.clone(), tls));
+            let handle = Handle::new();
+            tokio::spawn({
+                let handle = handle.clone();
+                async move {
+                    shutdown_signal().await;
+                    handle.graceful_shutdown(None);
+                }
+            });
+            axum_server::bind_rustls(addr, rustls)
+                .handle(handle)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+                .with_context(|| format!("serve TLS on {addr}"))?;
+        }
+    }
+
+    info!("Shutting down, draining proofs for {:?}", config.shutdown_timeout);
+    let _ = deadline.set(std::time::Instant::now() + config.shutdown_timeout);
+    let _ = shutdown_tx.send(true);
+    queue.close();
+    if tokio::task::spawn_blocking(move || generator.join()).await.is_err() {
+        error!("proof generator panicked");
+    }
+    info!("Shutdown complete");
+    Ok(())
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("failed to install Ctrl+C handler");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+async fn reload_certificates(rustls: RustlsConfig, tls: TlsConfig) {
+    let mut modified = tls.modified();
+    let mut interval = tokio::time::interval(CERT_RELOAD_INTERVAL);
+    loop {
+        interval.tick().await;
+        let current = tls.modified();
+        if current == modified {
+            continue;
+        }
+        match rustls.reload_from_pem_file(&tls.cert, &tls.key).await {
+            Ok(()) => {
+                info!("Reloaded TLS certificate {}", tls.cert.display());
+                modified = current;
+            }
+            // Keep serving the old certificate, the files may be half written.
+            Err(err) => error!("reload TLS certificate: {}", err),
+        }
+    }
+}
+
+async fn rebroadcast<N: Network>(broadcaster: Broadcaster<N>, mut shutdown: watch::Receiver<bool>) {
+    let mut interval = tokio::time::interval(REBROADCAST_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = wait_shutdown(&mut shutdown) => break,
+        }
+        let broadcaster = broadcaster.clone();
+        // Node requests block, keep them off the runtime threads.
+        if tokio::task::spawn_blocking(move || broadcaster.rebroadcast_due()).await.is_err() {
+            error!("rebroadcast panicked");
+        }
+    }
+}
+
+async fn wait_shutdown(shutdown: &mut watch::Receiver<bool>) {
+    // Drop the returned guard here, it must not be held across an await point.
+    let _ = shutdown.wait_for(|shutdown| *shutdown).await;
+}
+
+async fn metrics_handler() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        METRICS.render(),
+    )
 }
 
 async fn ws_handler<N: Network>(
     ws: WebSocketUpgrade,
-    State(tx): State<Sender<Player<N>>>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| async {
-        if let Err(err) = handle_socket(socket, tx).await {
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    State(state): State<AppState<N>>,
+) -> axum::response::Response {
+    if let Err(err) = state.limits.connect(remote.ip()) {
+        info!("Refuse connection: {}", err);
+        return (StatusCode::TOO_MANY_REQUESTS, err.to_string()).into_response();
+    }
+    let max_message_size = state.limits.max_message_size();
+    // Larger messages fail the read, which drops the connection.
+    ws.max_message_size(max_message_size).max_frame_size(max_message_size).on_upgrade(move |socket| async move {
+        if let Err(err) = handle_socket(socket, remote, state).await {
+            METRICS.websocket_errors.inc();
             info!("handle socket: {}", err);
         }
     })
 }
 
-async fn handle_socket<N: Network>(mut socket: WebSocket, tx: Sender<Player<N>>) -> Result<()> {
-    let message = socket.recv().await;
-    let message = match message {
-        Some(message) => message?,
-        None => {
-            bail!("Connection broken");
-        }
-    };
-    let address = match message {
-        Message::Text(address) => Address::from_str(&address)?,
+async fn handle_socket<N: Network>(mut socket: WebSocket, remote: SocketAddr, state: AppState<N>) -> Result<()> {
+    let text = recv_handshake_text(&mut socket).await?;
+    let (version, address, game_id) = match decode_handshake::<N>(&text)? {
+        ClientMessage::Hello { version, address } => (version, address, None),
+        ClientMessage::Reconnect { version, address, game_id } => (version, address, Some(game_id)),
         _ => bail!("Not following protocol"),
     };
-    tx.send(Player::new(address, socket)).await?;
+    if !is_supported(version) {
+        let error = PlayerMessage::<N>::Error(format!(
+            "Unsupported protocol version {version}, server speaks {PROTOCOL_VERSION}"
+        ));
+        socket.send(Message::Text(encode_player_message(version, &error)?)).await?;
+        bail!("Unsupported protocol version {version}");
+    }
+    if version < SEALED_RECORDS_VERSION && state.records != RecordMode::Plaintext {
+        let error = PlayerMessage::<N>::Error(format!(
+            "Protocol version {SEALED_RECORDS_VERSION} is required, this server does not send plaintext records"
+        ));
+        socket.send(Message::Text(encode_player_message(version, &error)?)).await?;
+        bail!("Protocol version {version} needs plaintext records");
+    }
+    let proven = version >= SIGNED_HANDSHAKE_VERSION;
+    if proven {
+        prove_address(&mut socket, version, &address).await?;
+    }
+    if game_id.is_some() && !proven {
+        // Anyone can read an address and game id off /api/games, only the key holder may take the seat.
+        let error = PlayerMessage::<N>::Error(format!("Reconnecting needs protocol version {SIGNED_HANDSHAKE_VERSION}"));
+        socket.send(Message::Text(encode_player_message(version, &error)?)).await?;
+        bail!("Unproven reconnect of {address}");
+    }
+    // A reconnect takes up its own seat, it does not count as another game. Unproven
+    // addresses could be anyone's, their games are counted against the IP instead.
+    let admitted = state.limits.admit(&address, proven).and_then(|()| match game_id {
+        None => state.limits.claim_game(remote.ip(), proven.then_some(&address)).map(Some),
+        Some(_) => Ok(None),
+    });
+    let game_slot = match admitted {
+        Ok(game_slot) => game_slot,
+        Err(err) => {
+            let error = PlayerMessage::<N>::Error(err.to_string());
+            socket.send(Message::Text(encode_player_message(version, &error)?)).await?;
+            return Err(err);
+        }
+    };
+    match game_id {
+        Some(game_id) => state.seats.route(game_id, (address, version, socket, remote.ip())).await?,
+        None => {
+            let player = Player::new(address, proven, version, state.keepalive, socket, remote.ip(), game_slot);
+            if version != LEGACY_VERSION {
+                player.notify(PlayerMessage::Welcome(version)).await?;
+            }
+            state.players.send(player).await?;
+        }
+    }
     Ok(())
 }
 
+async fn recv_handshake_text(socket: &mut WebSocket) -> Result<String> {
+    match socket.recv().await {
+        Some(Ok(Message::Text(text))) => Ok(text),
+        Some(Ok(_)) => bail!("Not following protocol"),
+        Some(Err(err)) => Err(err.into()),
+        None => bail!("Connection broken"),
+    }
+}
+
+/// Has the client sign a fresh challenge with the private key of `address`.
+async fn prove_address<N: Network>(socket: &mut WebSocket, version: u32, address: &Address<N>) -> Result<()> {
+    let challenge = new_challenge();
+    socket.send(Message::Text(encode_player_message(version, &PlayerMessage::<N>::Challenge(challenge.clone()))?)).await?;
+    let text = tokio::time::timeout(CHALLENGE_TIMEOUT, recv_handshake_text(socket))
+        .await
+        .map_err(|_| anyhow!("{address} did not answer the challenge"))??;
+    match serde_json::from_str::<ClientMessage<N>>(&text) {
+        Ok(ClientMessage::Prove { signature }) if verify_challenge(address, &challenge, &signature) => Ok(()),
+        _ => {
+            let error = PlayerMessage::<N>::Error(format!("Could not verify the signature of {address}"));
+            socket.send(Message::Text(encode_player_message(version, &error)?)).await?;
+            bail!("{address} failed the challenge")
+        }
+    }
+}
+
 pub async fn run_server<N: Network, A: snarkvm::circuit::Aleo<Network = N>>(
     executor: Executor<N, ConsensusMemory<N>>,
+    queue: ProofQueue<N>,
+    games: GameRegistry<N>,
+    seats: Seats<N>,
+    records: RecordMode,
+    limits: Limits<N>,
+    mut shutdown: watch::Receiver<bool>,
 ) -> Sender<Player<N>> {
     let (tx, mut rx) = mpsc::channel(1024);
-    let proof_tx = start_generator(executor.clone());
-    let mut players = vec![];
+    // Read while waiting for an opponent, so one who leaves or goes idle is dropped instead of paired.
+    let mut waiting: Option<Player<N>> = None;
     tokio::spawn(async move {
         loop {
-            let player = rx.recv().await.unwrap();
-            players.push(player);
-            if players.len() == 2 {
-                let executor = executor.clone();
-                let player2 = players.pop().unwrap();
-                let player1 = players.pop().unwrap();
-                let proof_tx = proof_tx.clone();
-                tokio::spawn(async move {
-                        if let Err(err) = start_game::<N, A>(player1, player2, executor, proof_tx).await{
-                            error!("game over: {}",err);
-                        };
+            let player = tokio::select! {
+                player = rx.recv() => match player {
+                    Some(player) => player,
+                    None => break,
+                },
+                text = recv_waiting(&waiting) => {
+                    let player = waiting.as_ref().expect("only read while waiting");
+                    if let Err(err) = answer_waiting(player, text).await {
+                        info!("Player {} left before the game: {}", player.address(), err);
+                        waiting = None;
                     }
-                );
-            }
+                    continue;
+                },
+                _ = wait_shutdown(&mut shutdown) => break,
+            };
+            let Some(player1) = waiting.take() else {
+                waiting = Some(player);
+                continue;
+            };
+            let player2 = player;
+            let executor = executor.clone();
+            let queue = queue.clone();
+            let games = games.clone();
+            let seats = seats.clone();
+            let limits = limits.clone();
+            let shutdown = shutdown.clone();
+            let id = games.create(*player1.address(), *player2.address());
+            let reconnects = seats.register(id);
+            tokio::spawn(async move {
+                    // Held until the game is over, the players outlive it while its moves are proven.
+                    let _game_slots = [player1.take_game_slot(), player2.take_game_slot()];
+                    if let Err(err) = start_game::<N, A>(id, player1, player2, executor, queue.clone(), games.clone(), records, limits, reconnects, shutdown).await{
+                        error!("game over: {}",err);
+                    };
+                    seats.remove(id);
+                    games.finish(id);
+                    queue.finish_game(id);
+                }
+            );
+        }
+        if let Some(player) = waiting {
+            let _ = player.notify(PlayerMessage::Shutdown).await;
         }
     });
     tx
 }
 
+async fn recv_waiting<N: Network>(waiting: &Option<Player<N>>) -> Result<String> {
+    match waiting {
+        Some(player) => player.recv_text().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Answers a player waiting for an opponent, failing once the player is gone or resigned.
+async fn answer_waiting<N: Network>(player: &Player<N>, text: Result<String>) -> Result<()> {
+    match player.decode(&text?) {
+        Ok(ClientMessage::Ping { nonce }) => player.notify(PlayerMessage::Pong(nonce)).await,
+        Ok(ClientMessage::Resign) => bail!("Resigned"),
+        Ok(_) => player.notify(PlayerMessage::Error("Waiting for an opponent".to_string())).await,
+        Err(err) => player.notify(PlayerMessage::Error(err.to_string())).await,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn start_game<N: Network, A: snarkvm::circuit::Aleo<Network = N>>(
+    id: u64,
     player1: Player<N>,
     player2: Player<N>,
     executor: Executor<N, ConsensusMemory<N>>,
-    proof_tx: std::sync::mpsc::Sender<ProofRequest<N>>
+    queue: ProofQueue<N>,
+    games: GameRegistry<N>,
+    records: RecordMode,
+    limits: Limits<N>,
+    mut reconnects: mpsc::Receiver<Reconnect<N>>,
+    mut shutdown: watch::Receiver<bool>,
 ) -> Result<()> {
     info!("Start game {} {}", player1.address(), player2.address());
+    let _table = GaugeGuard::new(&METRICS.active_tables);
     let mut ct = ChessTable::new(*player1.address(), *player2.address());
-    player1.notify_start("A", player2.address()).await?;
-    player2.notify_start("B", player1.address()).await?;
+    player1.notify_start("A", player2.address(), id).await?;
+    player2.notify_start("B", player1.address(), id).await?;
+    // When each seat lost its connection, a dropped player may reconnect within RECONNECT_GRACE.
+    let mut disconnected: [Option<Instant>; 2] = [None, None];
     loop {
-        let requests = tokio::select! {
-            req = player1.recv_request() => {
-                req?
+        let reconnect_deadline = disconnected.iter().flatten().min().map(|since| *since + RECONNECT_GRACE);
+        let (seat, text) = tokio::select! {
+            text = player1.recv_text(), if disconnected[0].is_none() => (0, text),
+            text = player2.recv_text(), if disconnected[1].is_none() => (1, text),
+            Some((address, version, mut socket, remote)) = reconnects.recv() => {
+                let Some(seat) = [&player1, &player2].iter().position(|player| *player.address() == address) else {
+                    continue;
+                };
+                if disconnected[seat].is_none() {
+                    // The seat is still taken, a second connection of the same player must not steal it.
+                    let error = PlayerMessage::<N>::Error(format!("{address} is still connected to game {id}"));
+                    if let Ok(text) = encode_player_message(version, &error) {
+                        let _ = socket.send(Message::Text(text)).await;
+                    }
+                    continue;
+                }
+                let players = [&player1, &player2];
+                players[seat].reconnect(version, socket, remote).await;
+                disconnected[seat] = None;
+                info!("Player {} reconnected to game {}", address, id);
+                if version != LEGACY_VERSION {
+                    let _ = players[seat].notify(PlayerMessage::Welcome(version)).await;
+                }
+                let _ = players[1 - seat].notify(PlayerMessage::Reconnected(address)).await;
+                continue;
+            },
+            _ = sleep_until(reconnect_deadline.unwrap_or_else(Instant::now)), if reconnect_deadline.is_some() => {
+                bail!("Player did not reconnect to game {}", id);
             },
-            req = player2.recv_request() => {
-                req?
+            _ = wait_shutdown(&mut shutdown) => {
+                let _ = player1.notify(PlayerMessage::Shutdown).await;
+                let _ = player2.notify(PlayerMessage::Shutdown).await;
+                return Ok(());
+            }
+        };
+        let players = [&player1, &player2];
+        let (player, opponent) = (players[seat], players[1 - seat]);
+        let text = match text {
+            Ok(text) => text,
+            Err(err) => {
+                info!("Player {} disconnected from game {}: {}", player.address(), id, err);
+                disconnected[seat] = Some(Instant::now());
+                let _ = opponent.notify(PlayerMessage::Disconnected(*player.address())).await;
+                continue;
+            }
+        };
+        let (ip, address) = player.limits_key();
+        if !limits.message(ip, address) {
+            player.notify(PlayerMessage::Rejected(ErrorCode::RateLimited, "Too many messages, slow down".to_string())).await?;
+            continue;
+        }
+        let requests = match player.decode(&text) {
+            Ok(ClientMessage::Move { requests }) => requests,
+            Ok(ClientMessage::Resign) => {
+                player1.notify(PlayerMessage::Resigned(*player.address())).await?;
+                player2.notify(PlayerMessage::Resigned(*player.address())).await?;
+                return Ok(());
+            }
+            Ok(ClientMessage::Ping { nonce }) => {
+                player.notify(PlayerMessage::Pong(nonce)).await?;
+                continue;
+            }
+            Ok(ClientMessage::Chat { text }) => {
+                player1.notify(PlayerMessage::Chat(*player.address(), text.clone())).await?;
+                player2.notify(PlayerMessage::Chat(*player.address(), text)).await?;
+                continue;
+            }
+            Ok(ClientMessage::Hello { .. } | ClientMessage::Reconnect { .. } | ClientMessage::Prove { .. }) => {
+                player.notify(PlayerMessage::Error("Already in a game".to_string())).await?;
+                continue;
+            }
+            Err(err) => {
+                player.notify(PlayerMessage::Error(err.to_string())).await?;
+                continue;
+            }
+        };
+        if requests.is_empty() {
+            player.notify(PlayerMessage::Rejected(ErrorCode::MalformedInput, "A move needs at least one request".to_string())).await?;
+            continue;
+        }
+        let request = requests[0].clone();
+        let action = match action_from_request(request.clone()) {
+            Ok(action) => action,
+            Err(err) => {
+                player.notify(PlayerMessage::Rejected(ErrorCode::MalformedInput, err.to_string())).await?;
+                continue;
+            }
+        };
+        if let Err(violation) = ct.validate(&action) {
+            info!("Reject {} from {}: {}", request.function_name(), player.address(), violation);
+            player.notify(PlayerMessage::Rejected(violation.code, violation.message)).await?;
+            continue;
+        }
+        // Checked last, only valid moves cost a proof.
+        if !limits.proof(ip, address) {
+            info!("Reject {} from {}: move limit reached", request.function_name(), player.address());
+            player.notify(PlayerMessage::Rejected(ErrorCode::RateLimited, "Move limit reached, try again later".to_string())).await?;
+            continue;
+        }
+        let timer = METRICS.evaluate_latency.start_timer();
+        let response = executor.evaluate::<A>(requests.clone());
+        timer.observe_duration();
+        // Checks the table cannot make, like a spent or foreign record or a bad signature, are left to the VM.
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => {
+                info!("Reject {} from {}: {}", request.function_name(), player.address(), err);
+                player.notify(PlayerMessage::Rejected(ErrorCode::InvalidRequest, err.to_string())).await?;
+                continue;
             }
         };
-        let response = executor.evaluate::<A>(requests.clone())?;
         info!("Response: {:?}", response);
-        let action = action_from_request(requests[0].clone())?;
-        let status = ct.update_action(action)?;
+        let status = match ct.update_action(action) {
+            Ok(status) => status,
+            Err(err) => {
+                error!("Update game {} with {}: {}", id, request.function_name(), err);
+                player.notify(PlayerMessage::Error(err.to_string())).await?;
+                continue;
+            }
+        };
+        METRICS.moves.with_label_values(&[&request.function_name().to_string()]).inc();
+        let index = games.record_move(id, &request, status.clone());
         
         // Receiver transaction once proof is generated and broadcast then notify transacion_id to player
-        let (transaction_tx, transaction_rx) = oneshot::channel::<Transaction<N>>();
-        let authorization = Authorization::new(&requests.into_iter().collect_vec());
-        let _ = proof_tx.send((authorization, transaction_tx));
+        let requests = requests.into_iter().collect_vec();
+        let records1 = seal_records(&executor, records, &requests, &response, &player1, index).await;
+        let records2 = seal_records(&executor, records, &requests, &response, &player2, index).await;
+        let (transaction_tx, transaction_rx) = oneshot::channel::<ProofResult<N>>();
+        let authorization = Authorization::new(&requests);
+        if let Err(err) = queue.push(Some(id), authorization, transaction_tx) {
+            error!("queue move {} of game {}: {}", index, id, err);
+        }
         {   
             let player1 = player1.clone();
             let player2 = player2.clone();
-            let executor = executor.clone();
+            let games = games.clone();
             tokio::spawn(async move {
-                if let Ok(transaction) = transaction_rx.await {
-                    // Broadcast 
-                    // Notify 
-                    let result = executor.broadcast(&transaction);
-                    println!("Result: {:?}", result);
-                    let _ = player1.notify_tx_id(transaction.id()).await;
-                    let _ = player2.notify_tx_id(transaction.id()).await;
+                match transaction_rx.await {
+                    Ok(Ok(transaction_id)) => {
+                        games.record_transaction(id, index, transaction_id);
+                        // Notify 
+                        let _ = player1.notify_tx_id(transaction_id).await;
+                        let _ = player2.notify_tx_id(transaction_id).await;
+                    }
+                    Ok(Err(err)) => {
+                        let message = format!("Move {index} of game {id} was not broadcast: {err:#}");
+                        games.record_failure(id, index, format!("{err:#}"));
+                        let _ = player1.notify(PlayerMessage::Error(message.clone())).await;
+                        let _ = player2.notify(PlayerMessage::Error(message)).await;
+                    }
+                    // Dropped on shutdown, the request was persisted for the next start.
+                    Err(_) => {}
                 }
             });
         }
 
-        player1
-            .notify_status(status.clone(), response.clone())
-            .await?;
-        player2.notify_status(status, response).await?;
+        player1.notify_status(status.clone(), records1).await?;
+        player2.notify_status(status, records2).await?;
+    }
+}
+
+/// The records of a move for `player`. A record that cannot be sealed is reported to the player,
+/// who can still recover it from the chain, instead of ending the game.
+async fn seal_records<N: Network>(
+    executor: &Executor<N, ConsensusMemory<N>>,
+    mode: RecordMode,
+    requests: &[Request<N>],
+    response: &Response<N>,
+    player: &Player<N>,
+    index: usize,
+) -> Vec<SealedRecord<N>> {
+    match executor.seal_records(mode, requests, response, player.address()) {
+        Ok(records) => records,
+        Err(err) => {
+            error!("seal records of move {} for {}: {}", index, player.address(), err);
+            let message = format!("Your records of move {index} could not be sent, recover them from the chain: {err}");
+            let _ = player.notify(PlayerMessage::Error(message)).await;
+            vec![]
+        }
     }
 }
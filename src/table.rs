@@ -1,8 +1,14 @@
 
-use anyhow::{ensure, Result};
-use snarkvm::prelude::*;
+use std::fmt;
 
-use crate::requests::ActionRequest;
+use anyhow::{bail, ensure, Result};
+use serde::{Deserialize, Serialize};
+use snarkvm::prelude::{Address, Literal, Network, Plaintext, Request, Value};
+
+use crate::{
+    board::{validate_shot, Fleet},
+    requests::ActionRequest,
+};
 
 #[derive(Debug, Clone,Serialize, Deserialize)]
 pub enum Status {
@@ -14,6 +20,48 @@ pub enum Status {
     BTurn,
 }
 
+/// Why a request was rejected, by the table before it reached the VM or by the VM itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// The request does not belong to the caller's turn.
+    OutOfTurn,
+    /// An input is missing or has the wrong type.
+    MalformedInput,
+    /// The ships of `initialize_board` break the placement rules.
+    InvalidFleet,
+    /// `initialize_board` names someone other than the opponent.
+    WrongOpponent,
+    /// The `play` shot is not exactly one cell.
+    InvalidShot,
+    /// The `play` shot targets a cell the player already fired at.
+    RepeatedShot,
+    /// The player sent too many messages or moves, see [`crate::limits`].
+    RateLimited,
+    /// The VM refused the request, e.g. for a spent or foreign record or a bad signature.
+    InvalidRequest,
+}
+
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl Violation {
+    fn new(code: ErrorCode, message: impl fmt::Display) -> Self {
+        Self { code, message: message.to_string() }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for Violation {}
+
 // ChessTable is a statemachine
 pub struct ChessTable<N: Network> {
     player_a: Address<N>,
@@ -21,6 +69,9 @@ pub struct ChessTable<N: Network> {
     requests: Vec<Request<N>>,
     // responses: Vec<Response<N>>,
     status: Status,
+    // Cells each player has fired at, as bitboards.
+    shots_a: u64,
+    shots_b: u64,
 }
 
 impl<N: Network> ChessTable<N> {
@@ -31,7 +82,61 @@ impl<N: Network> ChessTable<N> {
             requests: vec![],
             // responses: vec![],
             status: Status::AInitialize,
+            shots_a: 0,
+            shots_b: 0,
+        }
+    }
+
+    /// Checks a request against the battleship rules before it is evaluated.
+    pub fn validate(&self, action: &ActionRequest<N>) -> Result<(), Violation> {
+        let (request, expected) = match action {
+            ActionRequest::Initialize(request) => (request, matches!(self.status, Status::AInitialize | Status::BInitialize)),
+            ActionRequest::Offer(request) => (request, matches!(self.status, Status::AOffer)),
+            ActionRequest::Start(request) => (request, matches!(self.status, Status::BStart)),
+            ActionRequest::Play(request) => (request, matches!(self.status, Status::ATurn | Status::BTurn)),
+        };
+        let turn = match self.status {
+            Status::AInitialize | Status::AOffer | Status::ATurn => &self.player_a,
+            Status::BInitialize | Status::BStart | Status::BTurn => &self.player_b,
+        };
+        if !expected || request.caller() != turn {
+            return Err(Violation::new(
+                ErrorCode::OutOfTurn,
+                format!("Game state: {:?}, recv request: {}", self.status, request.function_name()),
+            ));
+        }
+
+        match action {
+            ActionRequest::Initialize(request) => {
+                let mut ships = [0; 4];
+                for (index, ship) in ships.iter_mut().enumerate() {
+                    *ship = u64_input(request, index)?;
+                }
+                Fleet::from_bitboards(ships).map_err(|err| Violation::new(ErrorCode::InvalidFleet, err))?;
+                let opponent = match request.inputs().get(4) {
+                    Some(Value::Plaintext(Plaintext::Literal(Literal::Address(address), _))) => *address,
+                    _ => return Err(Violation::new(ErrorCode::MalformedInput, "Input 4 must be an address")),
+                };
+                let expected = if request.caller() == &self.player_a { &self.player_b } else { &self.player_a };
+                if &opponent != expected {
+                    return Err(Violation::new(ErrorCode::WrongOpponent, format!("Opponent must be {expected}")));
+                }
+            }
+            ActionRequest::Play(request) => {
+                let shot = u64_input(request, 2)?;
+                if shot.count_ones() != 1 {
+                    return Err(Violation::new(ErrorCode::InvalidShot, "A shot must target exactly one cell"));
+                }
+                validate_shot(self.shots(request.caller()), shot)
+                    .map_err(|err| Violation::new(ErrorCode::RepeatedShot, err))?;
+            }
+            ActionRequest::Offer(_) | ActionRequest::Start(_) => {}
         }
+        Ok(())
+    }
+
+    fn shots(&self, caller: &Address<N>) -> u64 {
+        if caller == &self.player_a { self.shots_a } else { self.shots_b }
     }
 
     pub fn update_action(&mut self, action: ActionRequest<N>) -> Result<Status> {
@@ -109,11 +214,13 @@ impl<N: Network> ChessTable<N> {
         match self.status {
             Status::ATurn => {
                 ensure!(request.caller() == &self.player_a);
+                self.shots_a |= u64_input(&request, 2)?;
                 self.requests.push(request);
                 self.status = Status::BTurn;
             }
             Status::BTurn => {
                 ensure!(request.caller() == &self.player_b);
+                self.shots_b |= u64_input(&request, 2)?;
                 self.requests.push(request);
                 self.status = Status::ATurn;
             }
@@ -126,3 +233,161 @@ impl<N: Network> ChessTable<N> {
         Ok(())
     }
 }
+
+fn u64_input<N: Network>(request: &Request<N>, index: usize) -> Result<u64, Violation> {
+    match request.inputs().get(index) {
+        Some(Value::Plaintext(Plaintext::Literal(Literal::U64(value), _))) => Ok(**value),
+        _ => Err(Violation::new(ErrorCode::MalformedInput, format!("Input {index} must be a u64"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use snarkvm::prelude::{Identifier, PrivateKey, ProgramID, Testnet3, ValueType};
+
+    use super::*;
+    use crate::{board::Cell, requests::action_from_request};
+
+    type N = Testnet3;
+
+    struct Game {
+        table: ChessTable<N>,
+        a: PrivateKey<N>,
+        b: PrivateKey<N>,
+    }
+
+    fn key() -> PrivateKey<N> {
+        PrivateKey::new(&mut ::rand::thread_rng()).unwrap()
+    }
+
+    fn address(private_key: &PrivateKey<N>) -> Address<N> {
+        Address::try_from(private_key).unwrap()
+    }
+
+    /// A request of `function` by `private_key`, addresses are passed as addresses and everything else as u64.
+    fn action(private_key: &PrivateKey<N>, function: &str, inputs: &[String]) -> ActionRequest<N> {
+        let types = inputs
+            .iter()
+            .map(|input| if input.starts_with("aleo1") { "address.private" } else { "u64.private" })
+            .map(|kind| ValueType::from_str(kind).unwrap())
+            .collect::<Vec<_>>();
+        let request = Request::sign(
+            private_key,
+            ProgramID::from_str("battleship.aleo").unwrap(),
+            Identifier::from_str(function).unwrap(),
+            inputs.iter().map(|input| Value::<N>::from_str(input).unwrap()),
+            &types,
+            &mut ::rand::thread_rng(),
+        )
+        .unwrap();
+        action_from_request(request).unwrap()
+    }
+
+    fn fleet() -> Vec<String> {
+        Fleet::random(&mut ::rand::thread_rng()).to_inputs().to_vec()
+    }
+
+    fn initialize(private_key: &PrivateKey<N>, ships: Vec<String>, opponent: Address<N>) -> ActionRequest<N> {
+        let mut inputs = ships;
+        inputs.push(opponent.to_string());
+        action(private_key, "initialize_board", &inputs)
+    }
+
+    fn shot(private_key: &PrivateKey<N>, cell: &str) -> ActionRequest<N> {
+        let bit = Cell::from_str(cell).unwrap().bit();
+        action(private_key, "play", &["0u64".to_string(), "0u64".to_string(), format!("{bit}u64")])
+    }
+
+    impl Game {
+        fn new() -> Self {
+            let (a, b) = (key(), key());
+            Self { table: ChessTable::new(address(&a), address(&b)), a, b }
+        }
+
+        /// Validates and applies `action`, which must be legal.
+        fn apply(&mut self, action: ActionRequest<N>) {
+            self.table.validate(&action).unwrap();
+            self.table.update_action(action).unwrap();
+        }
+
+        fn code(&self, action: ActionRequest<N>) -> ErrorCode {
+            self.table.validate(&action).unwrap_err().code
+        }
+
+        /// Plays until it is A's first turn.
+        fn started() -> Self {
+            let mut game = Self::new();
+            game.apply(initialize(&game.a, fleet(), address(&game.b)));
+            game.apply(initialize(&game.b, fleet(), address(&game.a)));
+            game.apply(action(&game.a, "offer_battleship", &[]));
+            game.apply(action(&game.b, "start_battleship", &[]));
+            game
+        }
+    }
+
+    #[test]
+    fn moves_out_of_turn_are_rejected() {
+        let game = Game::new();
+        assert_eq!(game.code(initialize(&game.b, fleet(), address(&game.a))), ErrorCode::OutOfTurn);
+        assert_eq!(game.code(shot(&game.a, "A1")), ErrorCode::OutOfTurn);
+        assert_eq!(game.code(initialize(&key(), fleet(), address(&game.b))), ErrorCode::OutOfTurn);
+
+        let game = Game::started();
+        assert_eq!(game.code(shot(&game.b, "A1")), ErrorCode::OutOfTurn);
+        assert_eq!(game.code(action(&game.a, "offer_battleship", &[])), ErrorCode::OutOfTurn);
+    }
+
+    #[test]
+    fn malformed_inputs_are_rejected() {
+        let game = Game::new();
+        let mut inputs = fleet();
+        inputs.push("1u64".to_string());
+        assert_eq!(game.code(action(&game.a, "initialize_board", &inputs)), ErrorCode::MalformedInput);
+        assert_eq!(game.code(action(&game.a, "initialize_board", &fleet()[..3])), ErrorCode::MalformedInput);
+        let mut inputs = fleet();
+        inputs[1] = address(&game.b).to_string();
+        inputs.push(address(&game.b).to_string());
+        assert_eq!(game.code(action(&game.a, "initialize_board", &inputs)), ErrorCode::MalformedInput);
+
+        let game = Game::started();
+        assert_eq!(game.code(action(&game.a, "play", &["0u64".to_string()])), ErrorCode::MalformedInput);
+    }
+
+    #[test]
+    fn illegal_fleets_are_rejected() {
+        let game = Game::new();
+        let opponent = address(&game.b);
+        assert_eq!(game.code(initialize(&game.a, vec!["0u64".to_string(); 4], opponent)), ErrorCode::InvalidFleet);
+        let mut ships = fleet();
+        ships[1] = ships[0].clone();
+        assert_eq!(game.code(initialize(&game.a, ships, opponent)), ErrorCode::InvalidFleet);
+    }
+
+    #[test]
+    fn boards_must_name_the_opponent() {
+        let game = Game::new();
+        assert_eq!(game.code(initialize(&game.a, fleet(), address(&game.a))), ErrorCode::WrongOpponent);
+        assert_eq!(game.code(initialize(&game.a, fleet(), address(&key()))), ErrorCode::WrongOpponent);
+    }
+
+    #[test]
+    fn shots_must_target_one_cell() {
+        let game = Game::started();
+        for bits in ["0u64", "3u64", "18446744073709551615u64"] {
+            let play = action(&game.a, "play", &["0u64".to_string(), "0u64".to_string(), bits.to_string()]);
+            assert_eq!(game.code(play), ErrorCode::InvalidShot, "{bits}");
+        }
+    }
+
+    #[test]
+    fn cells_can_only_be_fired_at_once() {
+        let mut game = Game::started();
+        game.apply(shot(&game.a, "C3"));
+        game.apply(shot(&game.b, "C3"));
+        assert_eq!(game.code(shot(&game.a, "C3")), ErrorCode::RepeatedShot);
+        game.apply(shot(&game.a, "C4"));
+        assert_eq!(game.code(shot(&game.b, "C3")), ErrorCode::RepeatedShot);
+    }
+}
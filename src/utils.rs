@@ -1,22 +1,214 @@
-use snarkvm::prelude::*;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Instant,
+};
 
-pub fn get_deployment<N: Network>(base: &str, program_id: &ProgramID<N>) -> Result<Option<Deployment<N>>>{
-    let transaction_id: N::TransactionID = 
-    ureq::get(&format!("{base}/testnet3/find/transactionID/deployment/{program_id}")).call()?.into_json()?;
-    let transaction: Transaction<N> = 
-    ureq::get(&format!("{base}/testnet3/transaction/{transaction_id}")).call()?.into_json()?;
-    let deployment = transaction.deployment().and_then(|deployment| Some(deployment.clone()));
-    Ok(deployment)
+use ::rand::thread_rng;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use snarkvm::prelude::{
+    ConsensusStorage, Deployment, FromBytes, Identifier, Network, Program, ProgramID, ProvingKey, Testnet3, ToBytes,
+    Transaction, VerifyingKey, VM,
+};
+use tracing::{info, warn};
+
+use crate::node::NodeClient;
+
+/// A network this build can talk to, picked at runtime by the binaries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetworkId {
+    Testnet3,
+}
+
+impl FromStr for NetworkId {
+    type Err = anyhow::Error;
+
+    fn from_str(network: &str) -> Result<Self> {
+        match network {
+            "testnet3" => Ok(NetworkId::Testnet3),
+            _ => bail!("Unknown network {network}, expected testnet3"),
+        }
+    }
 }
 
-pub fn vm_load_deployment<N: Network, C: ConsensusStorage<N>>(vm: &VM<N, C>, base: &str, program_id: &ProgramID<N>) -> Result<()>{
-    let program: Program<N> =
-    ureq::get(&format!("{base}/testnet3/program/{program_id}")).call()?.into_json()?;
+/// The path segment a node serves `N`'s REST API under, e.g. `{base}/testnet3/latest/height`.
+/// Follows the same mapping from `N::ID` as snarkVM's `Query`.
+pub fn network_path<N: Network>() -> Result<&'static str> {
+    match N::ID {
+        id if id == Testnet3::ID => Ok("testnet3"),
+        id => bail!("Unsupported network ID {id}"),
+    }
+}
+
+/// Loads `program_id` and its imports from their deployments on the node.
+pub fn vm_load_deployment<N: Network, C: ConsensusStorage<N>>(vm: &VM<N, C>, node: &NodeClient, program_id: &ProgramID<N>) -> Result<()>{
+    let program = node.program(program_id)?;
     for (dep_program, _) in program.imports() {
-        let deployment = get_deployment(&base, dep_program)?.expect("deployment should exsit");
+        let deployment = node.deployment(dep_program)?;
         vm.process().write().load_deployment(&deployment)?;
     }
-    let deployment = get_deployment(&base, program_id)?.expect("deployment should exsit");
+    let deployment = node.deployment(program_id)?;
+    vm.process().write().load_deployment(&deployment)?;
+    Ok(())
+}
+
+/// Loads `program_id` and its imports from source files named after the program, e.g. `{dir}/battleship.aleo`.
+/// Programs added from source have no keys yet, the process synthesizes them the first time a function is executed.
+pub fn vm_load_program_dir<N: Network, C: ConsensusStorage<N>>(vm: &VM<N, C>, dir: &Path, program_id: &ProgramID<N>) -> Result<()>{
+    if vm.contains_program(program_id) {
+        return Ok(());
+    }
+    let path = dir.join(program_id.to_string());
+    let source = fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+    let program = Program::<N>::from_str(&source)?;
+    ensure!(program.id() == program_id, "{} declares {}", path.display(), program.id());
+    for (dep_program, _) in program.imports() {
+        vm_load_program_dir(vm, dir, dep_program)?;
+    }
+    vm.process().write().add_program(&program)?;
+    Ok(())
+}
+
+/// Loads a deployment saved as JSON, either the `Deployment` itself or the deployment `Transaction`.
+/// Imports have to be loaded first.
+pub fn vm_load_deployment_file<N: Network, C: ConsensusStorage<N>>(vm: &VM<N, C>, path: &Path) -> Result<ProgramID<N>>{
+    let json = fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    let deployment = match serde_json::from_str::<Deployment<N>>(&json) {
+        Ok(deployment) => deployment,
+        Err(_) => serde_json::from_str::<Transaction<N>>(&json)?
+            .deployment()
+            .cloned()
+            .ok_or_else(|| anyhow!("{} is not a deployment transaction", path.display()))?,
+    };
     vm.process().write().load_deployment(&deployment)?;
+    Ok(*deployment.program_id())
+}
+
+/// Makes sure every function of `program_id` and its imports has its proving key, so the first
+/// proof of a function does not pay for synthesizing it. With a `cache_dir` the keys are read
+/// from and written to `{cache_dir}/{network}/{program_id}/`, which is cleared when the program changes.
+/// The cache is best effort: when it cannot be read or written the keys are only kept in memory.
+pub fn vm_prewarm_keys<N: Network, C: ConsensusStorage<N>, A: snarkvm::circuit::Aleo<Network = N>>(
+    vm: &VM<N, C>,
+    program_id: &ProgramID<N>,
+    cache_dir: Option<&Path>,
+) -> Result<()> {
+    let mut programs = vec![];
+    collect_programs(vm, program_id, &mut programs)?;
+    let process = vm.process();
+    let process = process.read();
+    for program_id in &programs {
+        let program = process.get_program(program_id)?;
+        let stack = process.get_stack(program_id)?;
+        let dir = match cache_dir.map(|dir| key_cache_dir(dir, program)) {
+            Some(Ok(dir)) => Some(dir),
+            Some(Err(err)) => {
+                warn!("{}: key cache unavailable, keys are kept in memory only: {}", program_id, err);
+                None
+            }
+            None => None,
+        };
+        let functions = program.functions().keys().collect::<Vec<_>>();
+        for (index, function) in functions.iter().enumerate() {
+            if stack.contains_proving_key(function) && stack.contains_verifying_key(function) {
+                continue;
+            }
+            let start = Instant::now();
+            let cached = match dir.as_deref().map(|dir| read_cached_keys::<N>(dir, function)) {
+                Some(Ok(keys)) => keys,
+                Some(Err(err)) => {
+                    warn!("{}/{}: ignoring cached keys: {}", program_id, function, err);
+                    None
+                }
+                None => None,
+            };
+            let source = match cached {
+                Some((proving_key, verifying_key)) => {
+                    stack.insert_proving_key(function, proving_key)?;
+                    // A deployment's verifying key stays, it is what the network checks against.
+                    if !stack.contains_verifying_key(function) {
+                        stack.insert_verifying_key(function, verifying_key)?;
+                    }
+                    "loaded"
+                }
+                None => {
+                    stack.synthesize_key::<A, _>(function, &mut thread_rng())?;
+                    if let Some(dir) = &dir {
+                        let written = write_cached_keys(dir, function, &stack.get_proving_key(function)?, &stack.get_verifying_key(function)?);
+                        if let Err(err) = written {
+                            warn!("{}/{}: could not cache keys: {}", program_id, function, err);
+                        }
+                    }
+                    "synthesized"
+                }
+            };
+            info!(
+                "{}/{}: keys {} in {:.1?} ({}/{})",
+                program_id,
+                function,
+                source,
+                start.elapsed(),
+                index + 1,
+                functions.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// `program_id` and its imports, imports first.
+fn collect_programs<N: Network, C: ConsensusStorage<N>>(
+    vm: &VM<N, C>,
+    program_id: &ProgramID<N>,
+    programs: &mut Vec<ProgramID<N>>,
+) -> Result<()> {
+    if programs.contains(program_id) {
+        return Ok(());
+    }
+    let imports = vm.process().read().get_program(program_id)?.imports().keys().copied().collect::<Vec<_>>();
+    for import in &imports {
+        collect_programs(vm, import, programs)?;
+    }
+    programs.push(*program_id);
     Ok(())
-}
\ No newline at end of file
+}
+
+fn key_cache_dir<N: Network>(cache_dir: &Path, program: &Program<N>) -> Result<PathBuf> {
+    let dir = cache_dir.join(network_path::<N>()?).join(program.id().to_string());
+    let source = program.to_string();
+    let path = dir.join("program.aleo");
+    if fs::read_to_string(&path).ok().as_deref() != Some(source.as_str()) {
+        // Keys of another version of the program.
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        fs::write(&path, source)?;
+    }
+    Ok(dir)
+}
+
+fn read_cached_keys<N: Network>(dir: &Path, function: &Identifier<N>) -> Result<Option<(ProvingKey<N>, VerifyingKey<N>)>> {
+    let (prover, verifier) = (dir.join(format!("{function}.prover")), dir.join(format!("{function}.verifier")));
+    if !prover.exists() || !verifier.exists() {
+        return Ok(None);
+    }
+    Ok(Some((ProvingKey::from_bytes_le(&fs::read(prover)?)?, VerifyingKey::from_bytes_le(&fs::read(verifier)?)?)))
+}
+
+fn write_cached_keys<N: Network>(
+    dir: &Path,
+    function: &Identifier<N>,
+    proving_key: &ProvingKey<N>,
+    verifying_key: &VerifyingKey<N>,
+) -> Result<()> {
+    // Written under a temporary name first, an interrupted write must not leave a truncated key behind.
+    for (extension, bytes) in [("prover", proving_key.to_bytes_le()?), ("verifier", verifying_key.to_bytes_le()?)] {
+        let path = dir.join(format!("{function}.{extension}"));
+        let partial = path.with_extension(format!("{extension}.partial"));
+        fs::write(&partial, bytes)?;
+        fs::rename(&partial, &path)?;
+    }
+    Ok(())
+}